@@ -0,0 +1,464 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::Connection;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::bot::Bot;
+use crate::db::DbPool;
+use crate::exchange::Exchange;
+use crate::notify::Kind;
+use crate::risk::Risk;
+use crate::strategy::{self, lending, Schedule};
+
+const DEFAULT_SCHEDULE: &str = "1h";
+
+// Share of one currency in an account, valued in the quote currency
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Bounds {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+}
+
+impl Bounds {
+    fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        for (key, value) in [("min", self.min), ("max", self.max)] {
+            if value.is_some_and(|v| !(v.is_finite() && v >= 0.)) {
+                errors.push(format!("{path}.{key}: must not be negative"));
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                errors.push(format!("{path}: min {min} is above max {max}"));
+            }
+        }
+    }
+}
+
+// `[allocation]`, compares the expected yield of the funding symbols of each account and
+// recommends moving capital to where it is highest. Recommendations are logged and sent to the
+// channels subscribed to `allocation`.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // hourly if omitted
+    pub schedule: Option<Schedule>,
+    // currency values are compared in, USD if omitted
+    pub quote: Option<String>,
+    // difference of the expected daily rates worth a move, 0.00005 if omitted
+    pub min_gain: Option<f64>,
+    // smallest move in the quote currency, 50 if omitted
+    pub min_move: Option<f64>,
+    // keyed by symbol, e.g. `[allocation.symbols.fBTC]`, unbounded if omitted
+    #[serde(default)]
+    pub symbols: HashMap<String, Bounds>,
+}
+
+impl Config {
+    pub fn schedule(&self) -> Schedule {
+        self.schedule.clone().unwrap_or_else(|| {
+            DEFAULT_SCHEDULE
+                .parse()
+                .expect("the default schedule is valid")
+        })
+    }
+
+    pub fn quote(&self) -> &str {
+        self.quote.as_deref().unwrap_or("USD")
+    }
+
+    pub fn min_gain(&self) -> f64 {
+        self.min_gain.unwrap_or(0.00005)
+    }
+
+    pub fn min_move(&self) -> f64 {
+        self.min_move.unwrap_or(50.)
+    }
+
+    pub fn bounds(&self, symbol: &str) -> Bounds {
+        // keys may have been lowercased by the configuration loader
+        self.symbols
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(symbol))
+            .map(|(_, bounds)| bounds.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        let quote = self.quote();
+        if quote.is_empty() || !quote.chars().all(|c| c.is_ascii_alphanumeric()) {
+            errors.push(format!(
+                "{path}.quote: {quote:?} is not a currency code, e.g. USD"
+            ));
+        }
+        if !(self.min_gain().is_finite() && self.min_gain() >= 0.) {
+            errors.push(format!("{path}.min_gain: must not be negative"));
+        }
+        if !(self.min_move().is_finite() && self.min_move() > 0.) {
+            errors.push(format!("{path}.min_move: must be positive"));
+        }
+        for (symbol, bounds) in &self.symbols {
+            bounds.validate(&format!("{path}.symbols.{symbol}"), errors);
+        }
+    }
+}
+
+// Logs and sends the recommended moves of every account with strategies, run on `schedule`.
+pub async fn run(bot: &Bot, config: &Config, db_pool: DbPool) -> Result<()> {
+    let exchanges = bot.exchanges();
+    let (config, notifier, risk) = (config.clone(), bot.notifier().clone(), bot.risk().clone());
+
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let conn = db_pool.get()?;
+        for exchange in exchanges
+            .iter()
+            .filter(|e| !e.params().strategies.is_empty())
+        {
+            let allocation = Allocation::collect(exchange, &risk, &conn, &config)?;
+            for error in &allocation.errors {
+                log::warn!("{} {}: {}", exchange.name(), exchange.account(), error);
+            }
+            if allocation.moves.is_empty() {
+                continue;
+            }
+            log::info!(
+                "{} {}: {} moves recommended",
+                exchange.name(),
+                exchange.account(),
+                allocation.moves.len()
+            );
+            notifier.notify(
+                Kind::Allocation,
+                &format!("{} {}", exchange.name(), exchange.account()),
+                format!("{} capital moves recommended", allocation.moves.len()),
+                allocation.render(),
+            );
+        }
+        Ok(())
+    })
+    .await?
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Holding {
+    pub symbol: String,
+    // of one unit in the quote currency
+    pub price: f64,
+    // values in the quote currency: available, offered and lent, and the part of it not lent
+    pub value: f64,
+    pub movable: f64,
+    // expected daily rate
+    pub rate: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl Holding {
+    fn room(&self) -> f64 {
+        (self.max - self.value).max(0.)
+    }
+
+    fn spare(&self) -> f64 {
+        self.movable.min(self.value - self.min).max(0.)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reason {
+    AboveMax,
+    BelowMin,
+    Yield,
+}
+
+impl Reason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::AboveMax => "above max",
+            Self::BelowMin => "below min",
+            Self::Yield => "higher yield",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Move {
+    pub from: String,
+    pub to: String,
+    // in the quote currency, and in the currency moved from
+    pub value: f64,
+    pub amount: f64,
+    // difference of the expected daily rates
+    pub gain: f64,
+    pub reason: Reason,
+}
+
+// Moves bringing every holding within its bounds, then capital from the lowest to the highest
+// yields while the rates differ by at least `min_gain`. Only movable value is moved and moves
+// smaller than `min_move` are left out.
+pub fn plan(mut holdings: Vec<Holding>, min_gain: f64, min_move: f64) -> Vec<Move> {
+    let mut moves = vec![];
+    // every move empties a source, fills a target or settles a bound
+    for _ in 0..holdings.len().pow(2) * 3 {
+        let Some((from, to, value, reason)) = next_move(&holdings, min_gain, min_move) else {
+            break;
+        };
+        holdings[from].value -= value;
+        holdings[from].movable -= value;
+        holdings[to].value += value;
+        holdings[to].movable += value;
+        moves.push(Move {
+            from: holdings[from].symbol.clone(),
+            to: holdings[to].symbol.clone(),
+            value,
+            amount: value / holdings[from].price,
+            gain: holdings[to].rate - holdings[from].rate,
+            reason,
+        });
+    }
+    moves
+}
+
+fn next_move(
+    holdings: &[Holding],
+    min_gain: f64,
+    min_move: f64,
+) -> Option<(usize, usize, f64, Reason)> {
+    let mut by_rate: Vec<usize> = (0..holdings.len()).collect();
+    by_rate.sort_by(|a, b| holdings[*b].rate.total_cmp(&holdings[*a].rate));
+
+    for (from, holding) in holdings.iter().enumerate() {
+        let excess = (holding.value - holding.max).min(holding.movable);
+        if excess < min_move {
+            continue;
+        }
+        if let Some(&to) = by_rate
+            .iter()
+            .find(|&&to| to != from && holdings[to].room() >= min_move)
+        {
+            return Some((from, to, excess.min(holdings[to].room()), Reason::AboveMax));
+        }
+    }
+    for (to, holding) in holdings.iter().enumerate() {
+        let deficit = holding.min - holding.value;
+        if deficit < min_move {
+            continue;
+        }
+        if let Some(&from) = by_rate
+            .iter()
+            .rev()
+            .find(|&&from| from != to && holdings[from].spare() >= min_move)
+        {
+            return Some((
+                from,
+                to,
+                deficit.min(holdings[from].spare()),
+                Reason::BelowMin,
+            ));
+        }
+    }
+    for &to in by_rate
+        .iter()
+        .filter(|&&to| holdings[to].room() >= min_move)
+    {
+        if let Some(&from) = by_rate.iter().rev().find(|&&from| {
+            holdings[to].rate - holdings[from].rate >= min_gain.max(f64::MIN_POSITIVE)
+                && holdings[from].spare() >= min_move
+        }) {
+            let value = holdings[from].spare().min(holdings[to].room());
+            return Some((from, to, value, Reason::Yield));
+        }
+    }
+    None
+}
+
+#[derive(Debug)]
+pub struct Allocation {
+    pub exchange: &'static str,
+    pub account: String,
+    pub quote: String,
+    pub mts: DateTime<Utc>,
+    pub holdings: Vec<Holding>,
+    pub moves: Vec<Move>,
+    // symbols left out, with why
+    pub errors: Vec<String>,
+}
+
+impl Allocation {
+    // Values the funding symbols of an account and plans the moves between them. The expected
+    // rate of a symbol is the mean of the rate estimated from stored trades and the funding
+    // stats of the exchange.
+    pub fn collect(
+        exchange: &Exchange,
+        risk: &Risk,
+        conn: &Connection,
+        config: &Config,
+    ) -> Result<Self> {
+        let client = exchange.lending_client(risk)?;
+        let quote = config.quote();
+        let mut holdings = vec![];
+        let mut errors = vec![];
+
+        for strategy in &exchange.params().strategies {
+            let strategy::Config::Lending(lending) = strategy;
+            let symbol = lending.symbol.as_str();
+            let holding = (|| -> Result<Holding> {
+                let currency = lending.currency();
+                let price = client.price(currency, quote)?;
+                if !(price.is_finite() && price > 0.) {
+                    return Err(anyhow!("no price of {} in {}", currency, quote));
+                }
+                let available = client.balance(symbol)?;
+                let offered: f64 = client.active_offers(symbol)?.iter().map(|o| o.amount).sum();
+                let lent: f64 = client.credits(symbol)?.iter().map(|c| c.amount).sum();
+                let yield_lend = client.info(symbol)?.yield_lend;
+                let rate =
+                    match lending::estimate_rate(conn, symbol, lending.market().outlier_mads()) {
+                        Ok(estimate) => (estimate + yield_lend) / 2.,
+                        Err(_) => yield_lend,
+                    };
+                let bounds = config.bounds(symbol);
+                Ok(Holding {
+                    symbol: symbol.into(),
+                    price,
+                    value: (available + offered + lent) * price,
+                    movable: (available + offered) * price,
+                    rate,
+                    min: bounds.min.unwrap_or(0.),
+                    max: bounds.max.unwrap_or(f64::INFINITY),
+                })
+            })();
+            match holding {
+                Ok(holding) => holdings.push(holding),
+                Err(e) => errors.push(format!("{symbol} left out: {e}")),
+            }
+        }
+        let moves = plan(holdings.clone(), config.min_gain(), config.min_move());
+
+        Ok(Self {
+            exchange: exchange.name(),
+            account: exchange.account().into(),
+            quote: quote.into(),
+            mts: Utc::now(),
+            holdings,
+            moves,
+            errors,
+        })
+    }
+
+    pub fn render(&self) -> String {
+        let pct = |rate: f64| format!("{:.4}%", rate * 100.);
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{} {}, {} UTC, values in {}",
+            self.exchange,
+            self.account,
+            self.mts.format("%Y-%m-%d %H:%M"),
+            self.quote
+        );
+        for h in &self.holdings {
+            let _ = writeln!(
+                out,
+                "  {}: {:.2} ({:.2} not lent), expected {} a day",
+                h.symbol,
+                h.value,
+                h.movable,
+                pct(h.rate)
+            );
+        }
+        for error in &self.errors {
+            let _ = writeln!(out, "  {error}");
+        }
+        let _ = writeln!(out, "moves:");
+        if self.moves.is_empty() {
+            let _ = writeln!(out, "  none");
+        }
+        for m in &self.moves {
+            let _ = writeln!(
+                out,
+                "  {} -> {}: {:.8} ({:.2}), {}, {:+.4}% a day",
+                m.from,
+                m.to,
+                m.amount,
+                m.value,
+                m.reason.as_str(),
+                m.gain * 100.
+            );
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{plan, Holding, Reason};
+
+    #[test]
+    fn planned_moves() {
+        let holding = |symbol: &str, value: f64, rate: f64| Holding {
+            symbol: symbol.into(),
+            price: 1.,
+            value,
+            movable: value,
+            rate,
+            min: 0.,
+            max: f64::INFINITY,
+        };
+        let moves = |holdings: Vec<Holding>| -> Vec<(String, String, f64, Reason)> {
+            plan(holdings, 0.00005, 50.)
+                .into_iter()
+                .map(|m| (m.from, m.to, m.value, m.reason))
+                .collect()
+        };
+
+        // everything goes to the highest yield, lent value stays
+        let mut btc = holding("fBTC", 1000., 0.0001);
+        btc.movable = 400.;
+        btc.price = 50000.;
+        let planned = plan(
+            vec![
+                holding("fUSD", 1000., 0.0003),
+                btc.clone(),
+                holding("fETH", 1000., 0.00028),
+            ],
+            0.00005,
+            50.,
+        );
+        assert_eq!(planned.len(), 1);
+        assert_eq!(planned[0].from, "fBTC");
+        assert_eq!(planned[0].to, "fUSD");
+        assert_eq!(planned[0].value, 400.);
+        assert_eq!(planned[0].amount, 0.008);
+        assert!((planned[0].gain - 0.0002).abs() < 1e-12);
+
+        // bounds first, then yield within them
+        let mut usd = holding("fUSD", 1000., 0.0003);
+        usd.max = 1500.;
+        let mut ust = holding("fUST", 30., 0.0001);
+        ust.min = 500.;
+        assert_eq!(
+            moves(vec![usd, holding("fETH", 1000., 0.0002), ust]),
+            vec![
+                ("fETH".into(), "fUST".into(), 470., Reason::BelowMin),
+                ("fETH".into(), "fUSD".into(), 500., Reason::Yield),
+            ]
+        );
+
+        // above max even into a lower yield, small differences are not worth a move
+        let mut usd = holding("fUSD", 1000., 0.0003);
+        usd.max = 800.;
+        assert_eq!(
+            moves(vec![usd, holding("fETH", 1000., 0.00027)]),
+            vec![("fUSD".into(), "fETH".into(), 200., Reason::AboveMax)]
+        );
+        assert!(moves(vec![
+            holding("fUSD", 10., 0.0003),
+            holding("fETH", 40., 0.0001)
+        ])
+        .is_empty());
+    }
+}
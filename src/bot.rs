@@ -0,0 +1,662 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use log::{debug, error, info, warn};
+use serde::Serialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio_cron_scheduler::{Job, JobScheduler};
+use uuid::Uuid;
+
+use crate::db::{self, DbPool};
+use crate::exchange::{Exchange, Params, RequestFailed};
+use crate::metrics;
+use crate::notify::{Kind, Notifier};
+use crate::risk::Risk;
+use crate::strategy::{self, Schedule, SharedStatus, Status, Strategy};
+
+type SharedStrategy = Arc<Mutex<Box<dyn Strategy + Send>>>;
+type JobFn =
+    Box<dyn FnMut(Uuid, JobScheduler) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+pub fn job(schedule: &Schedule, run: JobFn) -> Result<Job> {
+    Ok(match schedule {
+        Schedule::Interval(duration) => Job::new_repeated_async(*duration, run)?,
+        Schedule::Cron(cron) => Job::new_async(cron.as_ref().clone(), run)?,
+    })
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Step {
+    Sync,
+    Exec,
+}
+
+impl Step {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Sync => "sync",
+            Self::Exec => "exec",
+        }
+    }
+}
+
+// Runs one step of a strategy on the blocking thread pool. A step still running when the next
+// run of the same kind is due is not queued up, the late run is skipped instead; a step of the
+// other kind waits for it to finish. Once the bot is stopping no step runs anymore, while
+// paused or halted by the kill switch only syncing does.
+fn step(
+    entry: &Running,
+    stopping: Arc<AtomicBool>,
+    notifier: Notifier,
+    risk: Risk,
+    kind: Step,
+) -> JobFn {
+    let name = entry.name.clone();
+    let strategy = entry.strategy.clone();
+    let paused = entry.paused.clone();
+    let busy = match kind {
+        Step::Sync => entry.syncing.clone(),
+        Step::Exec => entry.executing.clone(),
+    };
+    let labels = [
+        entry.exchange.to_string(),
+        entry.account.clone(),
+        entry.symbol.clone(),
+        kind.as_str().to_string(),
+    ];
+    Box::new(move |_, _| {
+        let name = name.clone();
+        let strategy = strategy.clone();
+        let stopping = stopping.clone();
+        let paused = paused.clone();
+        let busy = busy.clone();
+        let labels = labels.clone();
+        let notifier = notifier.clone();
+        let risk = risk.clone();
+        Box::pin(async move {
+            if busy.swap(true, Ordering::SeqCst) {
+                debug!(
+                    "[{}] previous {} still running, skipped",
+                    name,
+                    kind.as_str()
+                );
+                return;
+            }
+            let task_name = name.clone();
+            let result = tokio::task::spawn_blocking(move || {
+                let mut strategy = strategy.lock().unwrap_or_else(|e| {
+                    warn!("[{}] recovering from a panicked tick", task_name);
+                    e.into_inner()
+                });
+                if stopping.load(Ordering::SeqCst)
+                    || (kind == Step::Exec && paused.load(Ordering::SeqCst))
+                {
+                    return Ok(());
+                }
+                if kind == Step::Exec {
+                    if let Some(reason) = risk.halted() {
+                        debug!("[{}] kill switch engaged, skipped: {}", task_name, reason);
+                        return Ok(());
+                    }
+                }
+
+                let labels: Vec<&str> = labels.iter().map(|l| l.as_str()).collect();
+                let timer = metrics::TICK_SECONDS
+                    .with_label_values(&labels)
+                    .start_timer();
+                let result = match kind {
+                    Step::Sync => strategy.sync(),
+                    Step::Exec => strategy.exec(),
+                };
+                timer.observe_duration();
+                if result.is_ok() {
+                    metrics::LAST_SUCCESS
+                        .with_label_values(&labels)
+                        .set(Utc::now().timestamp_millis() as f64 / 1000.);
+                }
+                let status = strategy.status();
+                let mut status = status.write();
+                match &result {
+                    Ok(()) if kind == Step::Sync => status.last_sync = Some(Utc::now()),
+                    Ok(()) => status.last_exec = Some(Utc::now()),
+                    Err(e) => status.fail(kind.as_str(), e),
+                }
+                if result.is_ok() {
+                    status.last_error = None;
+                    status.errors = 0;
+                } else if result.as_ref().is_err_and(|e| e.is::<RequestFailed>())
+                    && status.errors >= notifier.config().api_errors()
+                {
+                    notifier.notify(
+                        Kind::ApiErrors,
+                        &task_name,
+                        format!("{} failing", kind.as_str()),
+                        format!(
+                            "{} failed requests in a row, the last one: {}",
+                            status.errors,
+                            status.last_error.as_deref().unwrap_or_default()
+                        ),
+                    );
+                }
+                result
+            })
+            .await
+            .map_err(|e| anyhow!(e))
+            .and_then(|r| r);
+            busy.store(false, Ordering::SeqCst);
+
+            if let Err(e) = result {
+                error!("[{}] {:?}", name, e);
+            }
+        })
+    })
+}
+
+struct Running {
+    name: String,
+    exchange: &'static str,
+    account: String,
+    symbol: String,
+    strategy: SharedStrategy,
+    status: SharedStatus,
+    paused: Arc<AtomicBool>,
+    // whether a sync or an exec step is in flight
+    syncing: Arc<AtomicBool>,
+    executing: Arc<AtomicBool>,
+    cancel_on_exit: bool,
+    // what the strategy was built from
+    config: strategy::Config,
+    params: Params,
+    // sync and exec schedules the jobs were created with
+    schedules: (Schedule, Schedule),
+    jobs: Vec<Uuid>,
+}
+
+#[derive(Serialize)]
+pub struct StrategyInfo {
+    pub id: String,
+    pub exchange: &'static str,
+    pub account: String,
+    pub symbol: String,
+    pub paused: bool,
+    pub status: Status,
+}
+
+pub struct Bot {
+    sched: JobScheduler,
+    db_pool: DbPool,
+    exchanges: RwLock<Vec<Exchange>>,
+    strategies: tokio::sync::Mutex<Vec<Running>>,
+    stopping: Arc<AtomicBool>,
+    notifier: Notifier,
+    risk: Risk,
+}
+
+impl Bot {
+    pub async fn new(db_pool: DbPool, notifier: Notifier, risk: Risk) -> Result<Self> {
+        Ok(Self {
+            sched: JobScheduler::new().await?,
+            db_pool,
+            notifier,
+            risk,
+            exchanges: RwLock::new(vec![]),
+            strategies: tokio::sync::Mutex::new(vec![]),
+            stopping: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    // Brings the running strategies in line with `exchanges`: strategies no longer configured
+    // are unscheduled, new ones are scheduled and those whose configuration or credentials
+    // changed are rebuilt in place, after their running tick has finished. Nothing changes if a
+    // strategy fails to build or a job cannot be scheduled.
+    pub async fn apply(&self, exchanges: &[Exchange]) -> Result<()> {
+        let mut running = self.strategies.lock().await;
+
+        let mut wanted: Vec<(&Exchange, &strategy::Config, String)> = vec![];
+        for exchange in exchanges {
+            for config in &exchange.params().strategies {
+                let name = exchange.strategy_id(config);
+                if wanted.iter().any(|(_, _, n)| *n == name) {
+                    return Err(anyhow!("strategy {} is configured twice", name));
+                }
+                wanted.push((exchange, config, name));
+            }
+        }
+        let existing: Vec<Option<usize>> = wanted
+            .iter()
+            .map(|(_, _, name)| running.iter().position(|r| r.name == *name))
+            .collect();
+
+        // build the new strategies, those whose configuration or credentials changed are rebuilt
+        // only once their running tick has finished
+        let mut todo: Vec<Option<(Exchange, strategy::Config)>> = wanted
+            .iter()
+            .zip(&existing)
+            .map(|((exchange, config, _), idx)| {
+                let unchanged = idx.is_some_and(|idx| {
+                    running[idx].config == **config
+                        && running[idx].params.same_credentials(exchange.params())
+                });
+                (!unchanged).then(|| ((*exchange).clone(), (*config).clone()))
+            })
+            .collect();
+        let mut built: Vec<Option<Box<dyn Strategy + Send>>> = {
+            let new: Vec<Option<(Exchange, strategy::Config)>> = todo
+                .iter_mut()
+                .zip(&existing)
+                .map(|(todo, idx)| if idx.is_none() { todo.take() } else { None })
+                .collect();
+            let (db_pool, notifier, risk) = (
+                self.db_pool.clone(),
+                self.notifier.clone(),
+                self.risk.clone(),
+            );
+            tokio::task::spawn_blocking(move || {
+                new.into_iter()
+                    .map(|new| {
+                        new.map(|(exchange, config)| {
+                            exchange.strategy(&config, db_pool.clone(), &notifier, &risk)
+                        })
+                        .transpose()
+                    })
+                    .collect::<Result<Vec<_>>>()
+            })
+            .await??
+        };
+
+        // schedule new strategies and changed schedules, removing what was added on failure
+        let mut added: Vec<Uuid> = vec![];
+        let mut plans = vec![];
+        for ((exchange, config, name), (idx, strategy)) in
+            wanted.iter().zip(existing.iter().zip(built.iter_mut()))
+        {
+            let schedules = (config.sync_schedule(), config.exec_schedule());
+            let new = match idx {
+                Some(_) => None,
+                None => {
+                    let strategy = strategy.take().expect("new strategies are built");
+                    Some(Running {
+                        name: name.clone(),
+                        exchange: exchange.name(),
+                        account: exchange.account().into(),
+                        symbol: config.symbol().into(),
+                        status: strategy.status(),
+                        strategy: Arc::new(Mutex::new(strategy)),
+                        paused: Arc::new(AtomicBool::new(false)),
+                        syncing: Arc::new(AtomicBool::new(false)),
+                        executing: Arc::new(AtomicBool::new(false)),
+                        cancel_on_exit: false,
+                        config: (*config).clone(),
+                        params: exchange.params().clone(),
+                        schedules: schedules.clone(),
+                        jobs: vec![],
+                    })
+                }
+            };
+            let entry = new.as_ref().or(idx.map(|idx| &running[idx]));
+            let entry = entry.expect("strategies are either new or running");
+            let jobs = if new.is_some() || entry.schedules != schedules {
+                match self.schedule(entry, &schedules).await {
+                    Ok(jobs) => {
+                        added.extend(&jobs);
+                        Some(jobs)
+                    }
+                    Err(e) => {
+                        self.unschedule(&added).await;
+                        return Err(e);
+                    }
+                }
+            } else {
+                None
+            };
+            plans.push((new, jobs, schedules));
+        }
+
+        // rebuild changed strategies in place, keeping their status
+        let swaps: Vec<(usize, SharedStrategy, Exchange, strategy::Config)> = existing
+            .iter()
+            .zip(todo)
+            .filter_map(|(idx, todo)| {
+                let (idx, (exchange, config)) = (*idx).zip(todo)?;
+                Some((idx, running[idx].strategy.clone(), exchange, config))
+            })
+            .collect();
+        let (db_pool, notifier, risk) = (
+            self.db_pool.clone(),
+            self.notifier.clone(),
+            self.risk.clone(),
+        );
+        let swapped = tokio::task::spawn_blocking(move || {
+            // the replacements are built while holding the running instances, so they start
+            // from the cursors stored by the last tick; nothing is swapped if one fails
+            let mut guards: Vec<_> = swaps
+                .iter()
+                .map(|(_, shared, _, _)| shared.lock().unwrap_or_else(|e| e.into_inner()))
+                .collect();
+            let strategies = swaps
+                .iter()
+                .map(|(_, _, exchange, config)| {
+                    exchange.strategy(config, db_pool.clone(), &notifier, &risk)
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let statuses = swaps
+                .iter()
+                .zip(guards.iter_mut())
+                .zip(strategies)
+                .map(|(((idx, ..), guard), strategy)| {
+                    let status = strategy.status();
+                    *status.write() = guard.status().read().clone();
+                    **guard = strategy;
+                    (*idx, status)
+                })
+                .collect::<Vec<_>>();
+            Ok::<_, anyhow::Error>(statuses)
+        })
+        .await;
+        let statuses = match swapped
+            .map_err(|e| anyhow!(e))
+            .and_then(|statuses| statuses)
+        {
+            Ok(statuses) => statuses,
+            Err(e) => {
+                self.unschedule(&added).await;
+                return Err(e);
+            }
+        };
+
+        let mut previous: Vec<Option<Running>> = running.drain(..).map(Some).collect();
+        for (idx, status) in statuses {
+            if let Some(entry) = &mut previous[idx] {
+                entry.status = status;
+            }
+        }
+        for r in previous.iter().flatten() {
+            if !wanted.iter().any(|(_, _, name)| *name == r.name) {
+                self.unschedule(&r.jobs).await;
+                info!("[{}] removed", r.name);
+            }
+        }
+        for (((exchange, config, name), idx), (new, jobs, schedules)) in
+            wanted.into_iter().zip(existing).zip(plans)
+        {
+            let mut entry = match (new, idx) {
+                (Some(entry), _) => {
+                    info!("[{}] added", name);
+                    entry
+                }
+                (None, Some(idx)) => previous[idx].take().expect("each strategy runs once"),
+                (None, None) => unreachable!("strategies are either new or running"),
+            };
+            if let Some(jobs) = jobs {
+                self.unschedule(&entry.jobs).await;
+                entry.jobs = jobs;
+                entry.schedules = schedules;
+            }
+            entry.cancel_on_exit = exchange.params().cancel_on_exit.unwrap_or(false);
+            entry.config = config.clone();
+            entry.params = exchange.params().clone();
+            running.push(entry);
+        }
+        *self.exchanges.write().unwrap() = exchanges.to_vec();
+
+        Ok(())
+    }
+
+    // A job that cannot be removed is logged, there is no undoing what was applied before.
+    async fn unschedule(&self, jobs: &[Uuid]) {
+        for id in jobs {
+            if let Err(e) = self.sched.remove(id).await {
+                error!("failed to remove job {}: {:?}", id, e);
+            }
+        }
+    }
+
+    async fn schedule(
+        &self,
+        entry: &Running,
+        (sync, exec): &(Schedule, Schedule),
+    ) -> Result<Vec<Uuid>> {
+        let step = |kind| {
+            step(
+                entry,
+                self.stopping.clone(),
+                self.notifier.clone(),
+                self.risk.clone(),
+                kind,
+            )
+        };
+        let (sync_step, exec_step) = (step(Step::Sync), step(Step::Exec));
+
+        Ok(vec![
+            self.sched.add(job(sync, sync_step)?).await?,
+            self.sched.add(job(exec, exec_step)?).await?,
+        ])
+    }
+
+    pub fn notifier(&self) -> &Notifier {
+        &self.notifier
+    }
+
+    pub fn risk(&self) -> &Risk {
+        &self.risk
+    }
+
+    pub fn exchanges(&self) -> Vec<Exchange> {
+        self.exchanges.read().unwrap().clone()
+    }
+
+    pub async fn strategies(&self) -> Vec<StrategyInfo> {
+        self.strategies
+            .lock()
+            .await
+            .iter()
+            .map(|r| StrategyInfo {
+                id: r.name.clone(),
+                exchange: r.exchange,
+                account: r.account.clone(),
+                symbol: r.symbol.clone(),
+                paused: r.paused.load(Ordering::SeqCst),
+                status: r.status.read().clone(),
+            })
+            .collect()
+    }
+
+    // Stops or restarts placing offers for a strategy, which keeps syncing while paused.
+    // Returns false if no such strategy is running.
+    pub async fn set_paused(
+        &self,
+        exchange: &str,
+        account: &str,
+        symbol: &str,
+        paused: bool,
+    ) -> bool {
+        let running = self.strategies.lock().await;
+        let entry = running.iter().find(|r| {
+            r.exchange.eq_ignore_ascii_case(exchange) && r.account == account && r.symbol == symbol
+        });
+        match entry {
+            Some(entry) => {
+                entry.paused.store(paused, Ordering::SeqCst);
+                info!(
+                    "[{}] {}",
+                    entry.name,
+                    if paused { "paused" } else { "resumed" }
+                );
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub async fn add_job(&self, job: Job) -> Result<()> {
+        self.sched.add(job).await?;
+        Ok(())
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        self.sched.start().await?;
+        Ok(())
+    }
+
+    // Stops scheduling, waits for running steps to finish, cancels open offers of exchanges
+    // configured with `cancel_on_exit` and checkpoints the database.
+    pub async fn shutdown(&self) -> Result<()> {
+        self.sched.clone().shutdown().await?;
+        self.stopping.store(true, Ordering::SeqCst);
+
+        let strategies = std::mem::take(&mut *self.strategies.lock().await);
+        let db_pool = self.db_pool.clone();
+        tokio::task::spawn_blocking(move || {
+            info!("waiting for running ticks to finish");
+            // keep every strategy locked until the end, steps already queued skip once they
+            // get the lock
+            let mut guards: Vec<_> = strategies
+                .iter()
+                .map(|s| (s, s.strategy.lock().unwrap_or_else(|e| e.into_inner())))
+                .collect();
+
+            for (s, strategy) in guards.iter_mut().filter(|(s, _)| s.cancel_on_exit) {
+                match strategy.cancel_offers() {
+                    Ok(()) => info!("[{}] open offers cancelled", s.name),
+                    Err(e) => error!("[{}] failed to cancel offers: {:?}", s.name, e),
+                }
+            }
+
+            let conn = db_pool.get()?;
+            db::checkpoint(&conn)
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bot, Running};
+    use crate::db;
+    use crate::exchange::{Exchange, Params};
+    use crate::notify::Notifier;
+    use crate::risk::Risk;
+    use crate::strategy::{self, lending, Strategy};
+    use chrono::{DateTime, Utc};
+    use secrecy::Secret;
+
+    fn exchange(min_apy: f64, offer_schedule: &str) -> Exchange {
+        Exchange::Bitfinex(Params {
+            account: None,
+            api_key: Secret::new("key".into()),
+            api_secret: Secret::new("secret".into()),
+            strategies: vec![strategy::Config::Lending(lending::Config {
+                symbol: "fUSD".into(),
+                lending_size: None,
+                min_apy: Some(min_apy),
+                max_apy: None,
+                reserved_amount_1: None,
+                reserved_amount_2: None,
+                tiers: None,
+                sync_schedule: None,
+                offer_schedule: Some(offer_schedule.parse().unwrap()),
+                market: None,
+                sweep: None,
+            })],
+            cancel_on_exit: None,
+        })
+    }
+
+    // identifies the strategy instance behind the shared handle
+    fn instance(running: &Running) -> usize {
+        let strategy = running.strategy.lock().unwrap();
+        &**strategy as *const (dyn Strategy + Send) as *const () as usize
+    }
+
+    #[tokio::test]
+    async fn apply_rebuilds_only_changed_strategies() {
+        let path = std::env::temp_dir().join(format!("tradebot-apply-{}.db", std::process::id()));
+        let db_pool = db::get_pool(Some(db::Config {
+            path: path.to_string_lossy().into(),
+            ..Default::default()
+        }))
+        .unwrap();
+        let bot = Bot::new(db_pool, Notifier::disabled(), Risk::default())
+            .await
+            .unwrap();
+
+        bot.apply(&[exchange(0.0003, "1m")]).await.unwrap();
+        let (first, jobs) = {
+            let running = bot.strategies.lock().await;
+            (instance(&running[0]), running[0].jobs.clone())
+        };
+
+        bot.apply(&[exchange(0.0003, "1m")]).await.unwrap();
+        {
+            let running = bot.strategies.lock().await;
+            assert_eq!(instance(&running[0]), first);
+            assert_eq!(running[0].jobs, jobs);
+        }
+
+        bot.apply(&[exchange(0.0004, "1m")]).await.unwrap();
+        {
+            let running = bot.strategies.lock().await;
+            assert_ne!(instance(&running[0]), first);
+            assert_eq!(running[0].jobs, jobs);
+        }
+
+        bot.apply(&[exchange(0.0004, "2m")]).await.unwrap();
+        {
+            let running = bot.strategies.lock().await;
+            assert_ne!(running[0].jobs, jobs);
+            assert_eq!(running[0].jobs.len(), 2);
+        }
+
+        bot.apply(&[]).await.unwrap();
+        assert!(bot.strategies.lock().await.is_empty());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn apply_rebuilds_from_the_cursors_of_the_running_tick() {
+        let path = std::env::temp_dir().join(format!("tradebot-cursors-{}.db", std::process::id()));
+        let db_pool = db::get_pool(Some(db::Config {
+            path: path.to_string_lossy().into(),
+            ..Default::default()
+        }))
+        .unwrap();
+        let bot = Bot::new(db_pool.clone(), Notifier::disabled(), Risk::default())
+            .await
+            .unwrap();
+        bot.apply(&[exchange(0.0003, "1m")]).await.unwrap();
+        let (name, shared) = {
+            let running = bot.strategies.lock().await;
+            (running[0].name.clone(), running[0].strategy.clone())
+        };
+
+        // a tick holding the strategy moves its payouts cursor forward before it finishes
+        let mts = "2030-01-02T03:04:05Z".parse::<DateTime<Utc>>().unwrap();
+        let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+        let (release_tx, release_rx) = std::sync::mpsc::channel::<()>();
+        let tick = std::thread::spawn(move || {
+            let _strategy = shared.lock().unwrap();
+            locked_tx.send(()).unwrap();
+            release_rx.recv().unwrap();
+            let conn = db_pool.get().unwrap();
+            db::cursors::set(&conn, &name, "payouts.mts", mts).unwrap();
+        });
+        locked_rx.recv().unwrap();
+
+        let exchanges = [exchange(0.0004, "1m")];
+        let (applied, _) = tokio::join!(bot.apply(&exchanges), async {
+            tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+            release_tx.send(()).unwrap();
+        });
+        applied.unwrap();
+        tick.join().unwrap();
+
+        let running = bot.strategies.lock().await;
+        let strategy = running[0].strategy.lock().unwrap();
+        assert!(format!("{:?}", strategy).contains(&format!("payouts_mts: {:?}", mts)));
+        drop(strategy);
+        drop(running);
+        let _ = std::fs::remove_file(&path);
+    }
+}
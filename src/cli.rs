@@ -0,0 +1,667 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
+use secrecy::Secret;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::sync::Arc;
+
+use crate::allocate::Allocation;
+use crate::config;
+use crate::db::{self, DbConn, DbPool};
+use crate::digest::{self, Digest};
+use crate::exchange::Exchange;
+use crate::export;
+use crate::import;
+use crate::risk::Risk;
+use crate::secrets;
+use crate::strategy::lending::Api;
+
+#[derive(Parser)]
+#[clap(version = "0.1")]
+pub struct Opts {
+    #[clap(short, long, default_value = "config.toml")]
+    pub config: String,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Start the scheduler loop (default)
+    Run,
+    /// Run each strategy a single tick and exit
+    Once,
+    /// Show balances, active offers and credits per exchange and symbol
+    Status(Filter),
+    /// Manage active funding offers
+    Offers {
+        #[clap(subcommand)]
+        command: OffersCommand,
+    },
+    /// Show funding credits
+    Credits {
+        #[clap(subcommand)]
+        command: CreditsCommand,
+    },
+    /// Validate the configuration without connecting to the database or any exchange
+    CheckConfig,
+    /// Print the digest of the last 24 hours per account
+    Digest {
+        #[clap(short, long)]
+        exchange: Option<String>,
+        #[clap(short, long)]
+        account: Option<String>,
+        /// markdown or text
+        #[clap(short, long, default_value = "markdown")]
+        format: digest::Format,
+    },
+    /// Show the value and expected yield per funding symbol and the recommended moves
+    Allocate {
+        #[clap(short, long)]
+        exchange: Option<String>,
+        #[clap(short, long)]
+        account: Option<String>,
+    },
+    /// Show recorded trading decisions, newest first
+    Decisions {
+        #[clap(flatten)]
+        filter: Filter,
+        /// Only this action: inputs, submit, cancel or skip
+        #[clap(long)]
+        action: Option<String>,
+        /// RFC 3339 timestamp, inclusive
+        #[clap(long)]
+        since: Option<DateTime<Utc>>,
+        #[clap(short = 'n', long, default_value = "50")]
+        limit: usize,
+    },
+    /// Show recorded wallet transfers, newest first
+    Transfers {
+        #[clap(short, long)]
+        exchange: Option<String>,
+        #[clap(short, long)]
+        account: Option<String>,
+        /// Only this currency, e.g. USD
+        #[clap(short, long)]
+        currency: Option<String>,
+        /// RFC 3339 timestamp, inclusive
+        #[clap(long)]
+        since: Option<DateTime<Utc>>,
+        #[clap(short = 'n', long, default_value = "50")]
+        limit: usize,
+    },
+    /// Database maintenance
+    Db {
+        #[clap(subcommand)]
+        command: DbCommand,
+    },
+    /// Export stored data as CSV, JSON Lines or Parquet
+    Export {
+        /// trades, credits, provided, offers, candles or decisions
+        table: export::Table,
+        /// Only rows of these funding symbols, e.g. fUSD
+        #[clap(short, long)]
+        symbol: Vec<String>,
+        /// Only rows of these accounts, for credits, provided and offers
+        #[clap(short, long)]
+        account: Vec<String>,
+        /// RFC 3339 timestamp, inclusive
+        #[clap(long)]
+        start: Option<DateTime<Utc>>,
+        /// RFC 3339 timestamp, exclusive
+        #[clap(long)]
+        end: Option<DateTime<Utc>>,
+        /// csv, jsonl or parquet
+        #[clap(short, long, default_value = "csv")]
+        format: export::Format,
+        /// Output file, stdout if omitted
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+    /// Halt or allow all offer submissions and cancellations, also of a running bot
+    KillSwitch {
+        #[clap(subcommand)]
+        command: KillSwitchCommand,
+    },
+    /// Manage the encrypted secrets file configured in [secrets]
+    Secrets {
+        #[clap(subcommand)]
+        command: SecretsCommand,
+    },
+    /// Import historical funding trades from CSV or JSON dumps
+    Import {
+        /// Funding symbol, e.g. fUSD
+        #[clap(short, long)]
+        symbol: String,
+        /// csv or json, guessed from the file extension if omitted
+        #[clap(short, long)]
+        format: Option<import::Format>,
+        file: String,
+    },
+}
+
+#[derive(Args)]
+pub struct Filter {
+    /// Only this exchange, e.g. bitfinex
+    #[clap(short, long)]
+    pub exchange: Option<String>,
+    /// Only this account, e.g. main
+    #[clap(short, long)]
+    pub account: Option<String>,
+    /// Only this funding symbol, e.g. fUSD
+    #[clap(short, long)]
+    pub symbol: Option<String>,
+}
+
+#[derive(Subcommand)]
+pub enum OffersCommand {
+    /// List active offers
+    List(Filter),
+    /// Cancel an offer by id
+    Cancel {
+        id: u32,
+        #[clap(short, long)]
+        exchange: Option<String>,
+        #[clap(short, long)]
+        account: Option<String>,
+    },
+    /// Cancel all active offers
+    CancelAll(Filter),
+}
+
+#[derive(Subcommand)]
+pub enum CreditsCommand {
+    /// List funding credits
+    List {
+        #[clap(flatten)]
+        filter: Filter,
+        /// Closed credits instead of active ones
+        #[clap(long)]
+        history: bool,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum SecretsCommand {
+    /// Store a secret, reading the value from the first line of stdin
+    Set { name: String },
+    /// Delete a secret
+    Remove { name: String },
+    /// List the names of stored secrets
+    List,
+}
+
+#[derive(Subcommand)]
+pub enum KillSwitchCommand {
+    /// Halt all writes until released
+    Engage {
+        /// Recorded in the kill switch file
+        #[clap(default_value = "engaged from the command line")]
+        reason: String,
+    },
+    /// Allow writes again
+    Release,
+    /// Show whether the kill switch is engaged
+    Status,
+}
+
+#[derive(Subcommand)]
+pub enum DbCommand {
+    /// Show row counts and size per table and symbol
+    Stats,
+    /// Apply the retention policy now
+    Prune,
+}
+
+impl Filter {
+    fn exchange(&self, exchange: &Exchange) -> bool {
+        self.exchange
+            .as_ref()
+            .is_none_or(|name| name.eq_ignore_ascii_case(exchange.name()))
+            && self
+                .account
+                .as_ref()
+                .is_none_or(|account| account == exchange.account())
+    }
+
+    fn symbol(&self, symbol: &str) -> bool {
+        self.symbol.as_ref().is_none_or(|s| s == symbol)
+    }
+
+    // lending clients with the configured symbols of every exchange matching the filter
+    fn clients<'a>(
+        &'a self,
+        conf: &'a config::Config,
+    ) -> impl Iterator<Item = Result<(&'a Exchange, Arc<dyn Api>, Vec<&'a str>)>> + 'a {
+        let risk = risk(conf);
+        conf.exchanges
+            .iter()
+            .filter(|exch| self.exchange(exch))
+            .map(move |exch| {
+                let mut symbols: Vec<&str> = exch
+                    .params()
+                    .strategies
+                    .iter()
+                    .map(|s| s.symbol())
+                    .filter(|s| self.symbol(s))
+                    .collect();
+                // keeps the configured order
+                let mut seen = HashSet::new();
+                symbols.retain(|s| seen.insert(*s));
+                Ok((exch, exch.lending_client(&risk)?, symbols))
+            })
+    }
+}
+
+// Runs a one-shot command.
+pub fn exec(command: Command, conf: &config::Config, db_pool: DbPool) -> Result<()> {
+    match command {
+        Command::Run => unreachable!("handled by the scheduler loop"),
+        Command::Once => {
+            for exch in &conf.exchanges {
+                exch.tick(db_pool.clone(), &risk(conf))?;
+            }
+        }
+        Command::Status(filter) => status(conf, &filter)?,
+        Command::Offers { command } => match command {
+            OffersCommand::List(filter) => list_offers(conf, &filter)?,
+            OffersCommand::Cancel {
+                id,
+                exchange,
+                account,
+            } => {
+                let filter = Filter {
+                    exchange,
+                    account,
+                    symbol: None,
+                };
+                let exchanges: Vec<&Exchange> = conf
+                    .exchanges
+                    .iter()
+                    .filter(|exch| filter.exchange(exch))
+                    .collect();
+                match exchanges[..] {
+                    [exch] => {
+                        exch.lending_client(&risk(conf))?.cancel_offer(id)?;
+                    }
+                    [] => return Err(anyhow!("no matching exchange configured")),
+                    _ => {
+                        return Err(anyhow!(
+                            "multiple accounts configured, use --exchange and --account"
+                        ))
+                    }
+                }
+                println!("cancelled offer {id}");
+            }
+            OffersCommand::CancelAll(filter) => {
+                for item in filter.clients(conf) {
+                    let (exch, client, symbols) = item?;
+                    for symbol in symbols {
+                        for offer in client.active_offers(symbol)? {
+                            client.cancel_offer(offer.id)?;
+                            println!(
+                                "{} {} {}: cancelled offer {}",
+                                exch.name(),
+                                exch.account(),
+                                symbol,
+                                offer.id
+                            );
+                        }
+                    }
+                }
+            }
+        },
+        Command::Credits { command } => match command {
+            CreditsCommand::List { filter, history } => list_credits(conf, &filter, history)?,
+        },
+        Command::CheckConfig => unreachable!("handled before opening the database"),
+        Command::Secrets { .. } => unreachable!("handled before loading the configuration"),
+        Command::KillSwitch { .. } => unreachable!("handled before opening the database"),
+        Command::Digest {
+            exchange,
+            account,
+            format,
+        } => {
+            let filter = Filter {
+                exchange,
+                account,
+                symbol: None,
+            };
+            let conn = db_pool.get()?;
+            for exch in conf
+                .exchanges
+                .iter()
+                .filter(|exch| filter.exchange(exch) && !exch.params().strategies.is_empty())
+            {
+                print!(
+                    "{}",
+                    Digest::collect(exch, &risk(conf), &conn, vec![])?.render(format)
+                );
+            }
+        }
+        Command::Allocate { exchange, account } => {
+            let filter = Filter {
+                exchange,
+                account,
+                symbol: None,
+            };
+            let config = conf.allocation.clone().unwrap_or_default();
+            let conn = db_pool.get()?;
+            for exch in conf
+                .exchanges
+                .iter()
+                .filter(|exch| filter.exchange(exch) && !exch.params().strategies.is_empty())
+            {
+                print!(
+                    "{}",
+                    Allocation::collect(exch, &risk(conf), &conn, &config)?.render()
+                );
+            }
+        }
+        Command::Decisions {
+            filter,
+            action,
+            since,
+            limit,
+        } => {
+            let conn = db_pool.get()?;
+            let filter = db::decisions::Filter {
+                exchange: filter.exchange,
+                account: filter.account,
+                symbol: filter.symbol,
+                action,
+                since,
+                limit,
+            };
+            print_decisions(&conn, &filter)?
+        }
+        Command::Transfers {
+            exchange,
+            account,
+            currency,
+            since,
+            limit,
+        } => {
+            let conn = db_pool.get()?;
+            let filter = db::transfers::Filter {
+                exchange,
+                account,
+                currency,
+                since,
+                limit,
+            };
+            print_transfers(&conn, &filter)?
+        }
+        Command::Db { command } => {
+            let conn = db_pool.get()?;
+            match command {
+                DbCommand::Stats => print_stats(&conn)?,
+                DbCommand::Prune => {
+                    let retention = conf
+                        .retention
+                        .as_ref()
+                        .ok_or_else(|| anyhow!("no retention policy configured"))?;
+                    db::retention::prune(&conn, retention)?
+                }
+            }
+        }
+        Command::Export {
+            table,
+            symbol,
+            account,
+            start,
+            end,
+            format,
+            output,
+        } => {
+            let conn = db_pool.get()?;
+            let filter = export::Filter {
+                symbols: symbol,
+                accounts: account,
+                start,
+                end,
+            };
+            let count = match output {
+                Some(path) => export::export(
+                    &conn,
+                    table,
+                    &filter,
+                    format,
+                    BufWriter::new(File::create(path)?),
+                )?,
+                None => export::export(
+                    &conn,
+                    table,
+                    &filter,
+                    format,
+                    BufWriter::new(std::io::stdout()),
+                )?,
+            };
+            log::info!("exported {} rows", count);
+        }
+        Command::Import {
+            symbol,
+            format,
+            file,
+        } => {
+            let format = format.unwrap_or(if file.ends_with(".json") {
+                import::Format::Json
+            } else {
+                import::Format::Csv
+            });
+            let mut conn = db_pool.get()?;
+            let summary = import::import(
+                &mut conn,
+                &symbol,
+                format,
+                BufReader::new(File::open(&file)?),
+            )?;
+            println!(
+                "read {}, inserted {}, duplicates {}, rejected {}",
+                summary.read, summary.inserted, summary.duplicates, summary.rejected
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn risk(conf: &config::Config) -> Risk {
+    Risk::new(conf.risk.clone().unwrap_or_default())
+}
+
+pub fn kill_switch(command: KillSwitchCommand, conf: &config::Config) -> Result<()> {
+    let risk = risk(conf);
+    match command {
+        KillSwitchCommand::Engage { reason } => {
+            risk.engage(&reason)?;
+            println!("kill switch engaged: {}", risk.config().kill_switch());
+        }
+        KillSwitchCommand::Release => match risk.release()? {
+            true => println!("kill switch released"),
+            false => println!("kill switch was not engaged"),
+        },
+        KillSwitchCommand::Status => match risk.halted() {
+            Some(reason) => println!("engaged: {reason}"),
+            None => println!("released"),
+        },
+    }
+    Ok(())
+}
+
+pub fn secrets(command: SecretsCommand, config_file: &str) -> Result<()> {
+    let mut store = secrets::Store::open(&config::Config::secrets_from_file(config_file)?)?;
+    match command {
+        SecretsCommand::Set { name } => {
+            let mut value = String::new();
+            std::io::stdin().read_line(&mut value)?;
+            let value = value.trim_end_matches(['\r', '\n']).to_string();
+            if value.is_empty() {
+                return Err(anyhow!("no value given on stdin"));
+            }
+            store.set(name, Secret::new(value));
+            store.save()?;
+        }
+        SecretsCommand::Remove { name } => {
+            if !store.remove(&name) {
+                return Err(anyhow!("no secret named {}", name));
+            }
+            store.save()?;
+        }
+        SecretsCommand::List => {
+            for name in store.names() {
+                println!("{name}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn status(conf: &config::Config, filter: &Filter) -> Result<()> {
+    for item in filter.clients(conf) {
+        let (exch, client, symbols) = item?;
+        for symbol in symbols {
+            let balance = client.balance(symbol)?;
+            let offers = client.active_offers(symbol)?;
+            let credits = client.credits(symbol)?;
+            println!(
+                "{} {} {}: available {:.2}, offered {:.2} in {} offers, lent {:.2} in {} credits",
+                exch.name(),
+                exch.account(),
+                symbol,
+                balance,
+                offers.iter().map(|o| o.amount).sum::<f64>(),
+                offers.len(),
+                credits.iter().map(|c| c.amount).sum::<f64>(),
+                credits.len(),
+            );
+        }
+    }
+    Ok(())
+}
+
+fn list_offers(conf: &config::Config, filter: &Filter) -> Result<()> {
+    println!(
+        "{:<10} {:<10} {:<8} {:>12} {:>12} {:>9} {:>6}  created",
+        "exchange", "account", "symbol", "id", "amount", "rate (%)", "period"
+    );
+    for item in filter.clients(conf) {
+        let (exch, client, symbols) = item?;
+        for symbol in symbols {
+            for o in client.active_offers(symbol)? {
+                println!(
+                    "{:<10} {:<10} {:<8} {:>12} {:>12.2} {:>9.4} {:>6}  {}",
+                    exch.name(),
+                    exch.account(),
+                    o.symbol,
+                    o.id,
+                    o.amount,
+                    o.rate * 100.,
+                    o.period,
+                    o.mts_created
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn list_credits(conf: &config::Config, filter: &Filter, history: bool) -> Result<()> {
+    println!(
+        "{:<10} {:<10} {:<8} {:>12} {:>12} {:>9} {:>6}  opened",
+        "exchange", "account", "symbol", "id", "amount", "rate (%)", "period"
+    );
+    for item in filter.clients(conf) {
+        let (exch, client, symbols) = item?;
+        for symbol in symbols {
+            let credits = if history {
+                client.credit_history(symbol, None)?
+            } else {
+                client.credits(symbol)?
+            };
+            for c in credits {
+                println!(
+                    "{:<10} {:<10} {:<8} {:>12} {:>12.2} {:>9.4} {:>6}  {}",
+                    exch.name(),
+                    exch.account(),
+                    c.symbol,
+                    c.id,
+                    c.amount,
+                    c.rate * 100.,
+                    c.period,
+                    c.mts_opening
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_decisions(conn: &DbConn, filter: &db::decisions::Filter) -> Result<()> {
+    println!(
+        "{:<25} {:<10} {:<8} {:<6} {:>12} {:>9} {:>6} {:>12} {:<8} reason",
+        "time", "account", "symbol", "action", "amount", "rate (%)", "period", "offer", "status"
+    );
+    let opt = |value: Option<String>| value.unwrap_or_else(|| "-".into());
+    for d in db::decisions::query(conn, filter)? {
+        println!(
+            "{:<25} {:<10} {:<8} {:<6} {:>12} {:>9} {:>6} {:>12} {:<8} {}",
+            d.mts.format("%Y-%m-%d %H:%M:%S%.3f"),
+            d.account,
+            d.symbol,
+            d.action,
+            opt(d.amount.map(|a| format!("{a:.2}"))),
+            opt(d.rate.map(|r| format!("{:.4}", r * 100.))),
+            opt(d.period.map(|p| p.to_string())),
+            opt(d.offer_id.map(|id| id.to_string())),
+            opt(d.status),
+            match d.text {
+                Some(text) => format!("{}: {}", d.reason, text),
+                None => d.reason,
+            }
+        );
+    }
+    Ok(())
+}
+
+fn print_transfers(conn: &DbConn, filter: &db::transfers::Filter) -> Result<()> {
+    println!(
+        "{:<25} {:<10} {:<8} {:<8} {:<8} {:>16} {:<8} reason",
+        "time", "account", "currency", "from", "to", "amount", "status"
+    );
+    for t in db::transfers::query(conn, filter)? {
+        println!(
+            "{:<25} {:<10} {:<8} {:<8} {:<8} {:>16.8} {:<8} {}",
+            t.mts.format("%Y-%m-%d %H:%M:%S%.3f"),
+            t.account,
+            t.currency,
+            t.from_wallet,
+            t.to_wallet,
+            t.amount,
+            t.status,
+            match t.text {
+                Some(text) => format!("{}: {}", t.reason, text),
+                None => t.reason,
+            }
+        );
+    }
+    Ok(())
+}
+
+fn print_stats(conn: &DbConn) -> Result<()> {
+    println!("{:<16} {:>12} {:>12}", "table", "rows", "size (KiB)");
+    for table in db::stats::stats(conn)? {
+        println!(
+            "{:<16} {:>12} {:>12.1}",
+            table.name,
+            table.rows,
+            table.size as f64 / 1024.
+        );
+        for (symbol, rows) in table.symbols {
+            println!("  {:<14} {:>12}", symbol, rows);
+        }
+    }
+    Ok(())
+}
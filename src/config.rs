@@ -1,13 +1,31 @@
-use anyhow::Result;
-use serde::Deserialize;
+use anyhow::{anyhow, Result};
+use serde::{de, de::DeserializeOwned, Deserialize, Deserializer};
 use std::sync::Arc;
 
+use crate::allocate;
+use crate::db::{self, retention};
+use crate::digest;
 use crate::exchange::Exchange;
+use crate::http;
+use crate::notify;
+use crate::risk;
+use crate::secrets;
+use crate::telemetry;
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
-    pub database: Option<String>,
+    pub allocation: Option<allocate::Config>,
+    #[serde(default, deserialize_with = "db::deserialize_config")]
+    pub database: Option<db::Config>,
+    pub digest: Option<digest::Config>,
     pub exchanges: Vec<Exchange>,
+    pub http: Option<http::Config>,
+    pub logging: Option<telemetry::Config>,
+    pub notifications: Option<notify::Config>,
+    pub retention: Option<retention::Config>,
+    pub risk: Option<risk::Config>,
+    pub secrets: Option<secrets::Config>,
 }
 
 impl Config {
@@ -15,6 +33,222 @@ impl Config {
         let conf = config::Config::builder()
             .add_source(config::File::with_name(file_name))
             .build()?;
-        Ok(Arc::new(conf.try_deserialize()?))
+        Ok(Arc::new(Self::from_config(conf)?))
+    }
+
+    fn from_config(conf: config::Config) -> Result<Self> {
+        let mut conf: Self = serde_path_to_error::deserialize(conf)
+            .map_err(|err| anyhow!(qualify(&err.path().to_string(), &err.inner().to_string())))?;
+        conf.resolve_secrets()?;
+        conf.validate()?;
+        Ok(conf)
+    }
+
+    // Only the `[secrets]` section, which has to be readable before the secrets it references
+    // exist.
+    pub fn secrets_from_file(file_name: &str) -> Result<secrets::Config> {
+        let conf = config::Config::builder()
+            .add_source(config::File::with_name(file_name))
+            .build()?;
+        match conf.get("secrets") {
+            Ok(secrets) => Ok(secrets),
+            Err(config::ConfigError::NotFound(_)) => {
+                Err(anyhow!("no [secrets] section in {}", file_name))
+            }
+            Err(err) => Err(anyhow!("secrets: {}", err)),
+        }
+    }
+
+    // Replaces `env:`, `file:` and `secrets:` references in credentials by their values.
+    fn resolve_secrets(&mut self) -> Result<()> {
+        let mut resolver = secrets::Resolver::new(self.secrets.as_ref());
+        let mut errors = vec![];
+
+        for (idx, exchange) in self.exchanges.iter_mut().enumerate() {
+            let params = exchange.params_mut();
+            for (key, value) in [
+                ("api_key", &mut params.api_key),
+                ("api_secret", &mut params.api_secret),
+            ] {
+                match resolver.resolve(value) {
+                    Ok(resolved) => *value = resolved,
+                    Err(err) => errors.push(format!("exchanges[{idx}].{key}: {err}")),
+                }
+            }
+        }
+
+        if let Some(token) = self.http.as_mut().and_then(|http| http.token.as_mut()) {
+            match resolver.resolve(token) {
+                Ok(resolved) => *token = resolved,
+                Err(err) => errors.push(format!("http.token: {err}")),
+            }
+        }
+        if let Some(notifications) = &mut self.notifications {
+            notifications.resolve_secrets("notifications", &mut resolver, &mut errors);
+        }
+
+        report(errors)
+    }
+
+    // Checks what deserializing cannot and reports every problem found, each prefixed with its
+    // path in the file, e.g. `exchanges[0].strategies[1].min_apy`.
+    pub fn validate(&self) -> Result<()> {
+        let mut errors = vec![];
+
+        if let Some(database) = &self.database {
+            database.validate("database", &mut errors);
+        }
+        for (idx, exchange) in self.exchanges.iter().enumerate() {
+            exchange.validate(&format!("exchanges[{idx}]"), &mut errors);
+            if self.exchanges[..idx].iter().any(|other| {
+                other.name() == exchange.name() && other.account() == exchange.account()
+            }) {
+                errors.push(format!(
+                    "exchanges[{idx}].account: {} account {:?} is already configured",
+                    exchange.name(),
+                    exchange.account()
+                ));
+            }
+        }
+        if let Some(allocation) = &self.allocation {
+            allocation.validate("allocation", &mut errors);
+        }
+        if let Some(digest) = &self.digest {
+            digest.validate("digest", &mut errors);
+            let subscribed = self
+                .notifications
+                .as_ref()
+                .is_some_and(|n| n.subscribed(notify::Kind::Digest));
+            if digest.path.is_none() && !subscribed {
+                errors.push(
+                    "digest: set digest.path or subscribe a notification channel to digest".into(),
+                );
+            }
+        }
+        if let Some(http) = &self.http {
+            http.validate("http", &mut errors);
+        }
+        if let Some(logging) = &self.logging {
+            logging.validate("logging", &mut errors);
+        }
+        if let Some(notifications) = &self.notifications {
+            notifications.validate("notifications", &mut errors);
+        }
+        if let Some(retention) = &self.retention {
+            retention.validate("retention", &mut errors);
+        }
+        if let Some(risk) = &self.risk {
+            risk.validate("risk", &mut errors);
+        }
+
+        report(errors)
+    }
+}
+
+fn report(errors: Vec<String>) -> Result<()> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("invalid configuration:\n  {}", errors.join("\n  ")))
+    }
+}
+
+// Splits the table of a `name` tagged enum into the name and the remaining fields. Deriving
+// `#[serde(tag = "name")]` buffers the fields, which hides where an error inside the variant
+// occurred; deserializing them with `variant` keeps the path.
+pub(crate) fn tagged<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<(String, config::Value), D::Error> {
+    let mut table = config::Map::<String, config::Value>::deserialize(deserializer)?;
+    let name = table
+        .remove("name")
+        .ok_or_else(|| de::Error::missing_field("name"))?
+        .into_string()
+        .map_err(de::Error::custom)?;
+    Ok((name, config::Value::new(None, table)))
+}
+
+pub(crate) fn variant<T: DeserializeOwned, E: de::Error>(fields: config::Value) -> Result<T, E> {
+    serde_path_to_error::deserialize(fields)
+        .map_err(|err| E::custom(qualify(&err.path().to_string(), &err.inner().to_string())))
+}
+
+// `strategies[0]` and `min_apy: invalid type` become `strategies[0].min_apy: invalid type`
+fn qualify(path: &str, message: &str) -> String {
+    match message.split_once(": ") {
+        Some((inner, message))
+            if inner
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "_.[]".contains(c)) =>
+        {
+            let path = path.trim_end_matches('.');
+            match inner.starts_with('[') || path.is_empty() {
+                true => format!("{path}{inner}: {message}"),
+                false => format!("{path}.{inner}: {message}"),
+            }
+        }
+        _ => format!("{path}: {message}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Config;
+
+    fn parse(toml: &str) -> anyhow::Result<Config> {
+        let conf = config::Config::builder()
+            .add_source(config::File::from_str(toml, config::FileFormat::Toml))
+            .build()?;
+        Config::from_config(conf)
+    }
+
+    #[test]
+    fn validate_config() {
+        let valid = r#"
+            [[exchanges]]
+            name = "Bitfinex"
+            api_key = "key"
+            api_secret = "secret"
+            [[exchanges.strategies]]
+            name = "Lending"
+            symbol = "fUSD"
+            min_apy = 0.0002
+        "#;
+        assert!(parse(valid).unwrap().database.is_none());
+
+        let err = parse(&format!("{valid}\nlending_sise = 200")).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("exchanges[0].strategies[0].lending_sise: unknown field"),
+            "{err}"
+        );
+
+        let err = parse(&format!("{valid}\nmax_apy = \"high\"")).unwrap_err();
+        assert!(
+            err.to_string()
+                .starts_with("exchanges[0].strategies[0].max_apy: invalid type"),
+            "{err}"
+        );
+
+        let err = parse(&format!(
+            "{valid}
+            lending_size = -1
+            max_apy = 0.0001
+            [[exchanges.strategies]]
+            name = \"Lending\"
+            symbol = \"fUSD\"
+            [retention]
+            trades_days = 0"
+        ))
+        .unwrap_err()
+        .to_string();
+        for expected in [
+            "exchanges[0].strategies[0].lending_size",
+            "exchanges[0].strategies[0].min_apy",
+            "exchanges[0].strategies[1]: fUSD",
+            "retention.trades_days",
+        ] {
+            assert!(err.contains(expected), "{expected} missing in {err}");
+        }
     }
 }
@@ -0,0 +1,256 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Timeframe {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Timeframe {
+    pub const ALL: [Timeframe; 3] = [Timeframe::Minute, Timeframe::Hour, Timeframe::Day];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Minute => "1m",
+            Self::Hour => "1h",
+            Self::Day => "1d",
+        }
+    }
+
+    // strftime format truncating a timestamp to the start of its bucket
+    fn bucket(&self) -> &'static str {
+        match self {
+            Self::Minute => "%Y-%m-%d %H:%M:00",
+            Self::Hour => "%Y-%m-%d %H:00:00",
+            Self::Day => "%Y-%m-%d 00:00:00",
+        }
+    }
+}
+
+impl fmt::Display for Timeframe {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Timeframe {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|t| t.as_str() == s)
+            .ok_or_else(|| anyhow!("unknown timeframe: {s}"))
+    }
+}
+
+#[derive(Debug)]
+pub struct Candle {
+    pub symbol: String,
+    pub mts: DateTime<Utc>,
+    pub open: f64,
+    pub high: f64,
+    pub low: f64,
+    pub close: f64,
+    pub volume: f64,
+    pub vwap: f64,
+    pub count: u32,
+}
+
+// Upsert a single trade (columns prefixed by `row`) into the candle of the given timeframe.
+// Statements are applied row by row, so the same SQL is shared by the insert trigger and the
+// initial backfill from existing trades.
+fn upsert(timeframe: Timeframe, row: &str, source: &str) -> String {
+    format!(
+        "INSERT INTO candles (
+            symbol, timeframe, mts, open, high, low, close,
+            volume, vwap, rate_sum, count, mts_first, mts_last
+        )
+        SELECT
+            {row}symbol, '{tf}', STRFTIME('{bucket}', {row}mts),
+            {row}rate, {row}rate, {row}rate, {row}rate,
+            ABS({row}amount), {row}rate, {row}rate, 1,
+            STRFTIME('%Y-%m-%d %H:%M:%f', {row}mts), STRFTIME('%Y-%m-%d %H:%M:%f', {row}mts)
+        {source}
+        ON CONFLICT (symbol, timeframe, mts) DO UPDATE SET
+            open = CASE WHEN excluded.mts_first < mts_first THEN excluded.open ELSE open END,
+            close = CASE WHEN excluded.mts_last >= mts_last THEN excluded.close ELSE close END,
+            high = MAX(high, excluded.high),
+            low = MIN(low, excluded.low),
+            vwap = COALESCE(
+                (vwap * volume + excluded.vwap * excluded.volume)
+                    / NULLIF(volume + excluded.volume, 0),
+                vwap
+            ),
+            volume = volume + excluded.volume,
+            rate_sum = rate_sum + excluded.rate_sum,
+            count = count + excluded.count,
+            mts_first = MIN(mts_first, excluded.mts_first),
+            mts_last = MAX(mts_last, excluded.mts_last);",
+        tf = timeframe.as_str(),
+        bucket = timeframe.bucket(),
+    )
+}
+
+pub fn init(conn: &Connection) -> Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'candles'",
+        params![],
+        |row| row.get(0),
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS candles (
+                    symbol      TEXT NOT NULL,
+                    timeframe   TEXT NOT NULL,
+                    mts         DATETIME NOT NULL,
+                    open        REAL NOT NULL,
+                    high        REAL NOT NULL,
+                    low         REAL NOT NULL,
+                    close       REAL NOT NULL,
+                    volume      REAL NOT NULL,
+                    vwap        REAL NOT NULL,
+                    rate_sum    REAL NOT NULL,
+                    count       INTEGER NOT NULL,
+                    mts_first   DATETIME NOT NULL,
+                    mts_last    DATETIME NOT NULL,
+                    PRIMARY KEY (symbol, timeframe, mts)
+                )",
+        params![],
+    )?;
+
+    let body: String = Timeframe::ALL
+        .into_iter()
+        .map(|tf| upsert(tf, "NEW.", "WHERE true"))
+        .collect();
+    conn.execute(
+        &format!(
+            "CREATE TRIGGER IF NOT EXISTS trades_candles AFTER INSERT ON trades
+            BEGIN
+                {body}
+            END"
+        ),
+        params![],
+    )?;
+
+    if !exists {
+        for tf in Timeframe::ALL {
+            conn.execute(&upsert(tf, "trades.", "FROM trades WHERE true"), params![])
+                .map_err(|err| anyhow!("failed to backfill {tf} candles: {:?}", err))?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn range(
+    conn: &Connection,
+    symbol: &str,
+    timeframe: Timeframe,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Result<Vec<Candle>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT symbol, mts, open, high, low, close, volume, vwap, count
+        FROM candles
+        WHERE
+            symbol = ?1 AND
+            timeframe = ?2 AND
+            mts >= STRFTIME(?3, ?4) AND
+            mts < DATETIME(?5)
+        ORDER BY mts",
+    )?;
+
+    let candles = stmt
+        .query_map(
+            params![symbol, timeframe.as_str(), timeframe.bucket(), start, end],
+            |row| {
+                Ok(Candle {
+                    symbol: row.get(0)?,
+                    mts: row.get(1)?,
+                    open: row.get(2)?,
+                    high: row.get(3)?,
+                    low: row.get(4)?,
+                    close: row.get(5)?,
+                    volume: row.get(6)?,
+                    vwap: row.get(7)?,
+                    count: row.get(8)?,
+                })
+            },
+        )?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(|err| anyhow!("failed to get candles: {:?}", err))?;
+
+    Ok(candles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{range, Timeframe};
+    use chrono::{TimeZone, Utc};
+    use rusqlite::{params, Connection};
+
+    fn insert(conn: &Connection, sec: u32, amount: f64, rate: f64) {
+        let mts = Utc.with_ymd_and_hms(2023, 1, 1, 12, 0, sec).unwrap();
+        conn.execute(
+            "INSERT INTO trades (symbol, mts, amount, rate, period) VALUES (?1, ?2, ?3, ?4, 2)",
+            params!["ffUSD", mts, amount, rate],
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn aggregate_out_of_order() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init(&conn).unwrap();
+
+        insert(&conn, 30, 100., 0.0003);
+        insert(&conn, 10, -300., 0.0001);
+        insert(&conn, 50, 100., 0.0005);
+        insert(&conn, 20, 500., 0.0002);
+
+        let start = Utc.with_ymd_and_hms(2023, 1, 1, 0, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2023, 1, 2, 0, 0, 0).unwrap();
+
+        for tf in Timeframe::ALL {
+            let candles = range(&conn, "ffUSD", tf, start, end).unwrap();
+            assert_eq!(candles.len(), 1);
+
+            let c = &candles[0];
+            assert_eq!(c.open, 0.0001);
+            assert_eq!(c.close, 0.0005);
+            assert_eq!(c.high, 0.0005);
+            assert_eq!(c.low, 0.0001);
+            assert_eq!(c.volume, 1000.);
+            assert_eq!(c.count, 4);
+            assert!((c.vwap - 0.00021).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn backfill_existing_trades() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init(&conn).unwrap();
+
+        insert(&conn, 10, 100., 0.0001);
+        insert(&conn, 20, 100., 0.0003);
+        conn.execute_batch("DROP TABLE candles; DROP TRIGGER trades_candles;")
+            .unwrap();
+        crate::db::init(&conn).unwrap();
+
+        let start = Utc.with_ymd_and_hms(2023, 1, 1, 12, 0, 0).unwrap();
+        let end = Utc.with_ymd_and_hms(2023, 1, 1, 12, 1, 0).unwrap();
+        let candles = range(&conn, "ffUSD", Timeframe::Minute, start, end).unwrap();
+
+        assert_eq!(candles.len(), 1);
+        assert_eq!(candles[0].count, 2);
+        assert_eq!(candles[0].open, 0.0001);
+        assert_eq!(candles[0].close, 0.0003);
+        assert!((candles[0].vwap - 0.0002).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::{anyhow, Result};
+use rusqlite::{params, types::FromSql, Connection, OptionalExtension, ToSql};
+
+// Sync positions of a strategy, e.g. the last stored trade, so that a restarted or delayed
+// strategy resumes where it left off. `strategy` identifies the strategy instance, see
+// `Exchange::strategy_id`.
+pub fn init(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS cursors (
+                    strategy    TEXT NOT NULL,
+                    name        TEXT NOT NULL,
+                    value       TEXT NOT NULL,
+                    PRIMARY KEY (strategy, name)
+                )",
+        params![],
+    )?;
+
+    // ids were `<exchange> <symbol>` before accounts, those belong to the main account
+    conn.execute(
+        "UPDATE cursors
+        SET strategy = REPLACE(strategy, ' ', ' main ')
+        WHERE strategy NOT LIKE '% % %'",
+        params![],
+    )?;
+
+    Ok(())
+}
+
+pub fn get<T: FromSql>(conn: &Connection, strategy: &str, name: &str) -> Result<Option<T>> {
+    conn.prepare_cached("SELECT value FROM cursors WHERE strategy = ?1 AND name = ?2")?
+        .query_row(params![strategy, name], |row| row.get(0))
+        .optional()
+        .map_err(|err| anyhow!("failed to read cursor {strategy}/{name}: {:?}", err))
+}
+
+pub fn set<T: ToSql>(conn: &Connection, strategy: &str, name: &str, value: T) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT INTO cursors (strategy, name, value) VALUES (?1, ?2, ?3)
+        ON CONFLICT (strategy, name) DO UPDATE SET value = excluded.value",
+    )?
+    .execute(params![strategy, name, value])
+    .map_err(|err| anyhow!("failed to write cursor {strategy}/{name}: {:?}", err))?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+    use rusqlite::Connection;
+
+    #[test]
+    fn cursor_roundtrip() {
+        let conn = Connection::open_in_memory().unwrap();
+        super::init(&conn).unwrap();
+
+        let mts = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        assert_eq!(
+            super::get::<DateTime<Utc>>(&conn, "Bitfinex main fUSD", "trades.mts").unwrap(),
+            None
+        );
+        super::set(&conn, "Bitfinex main fUSD", "trades.mts", mts).unwrap();
+        super::set(
+            &conn,
+            "Bitfinex main fUSD",
+            "trades.mts",
+            mts + chrono::Duration::hours(1),
+        )
+        .unwrap();
+        assert_eq!(
+            super::get(&conn, "Bitfinex main fUSD", "trades.mts").unwrap(),
+            Some(mts + chrono::Duration::hours(1))
+        );
+
+        // written before accounts existed
+        super::set(&conn, "Bitfinex fEUR", "trades.mts", mts).unwrap();
+        super::init(&conn).unwrap();
+        assert_eq!(
+            super::get(&conn, "Bitfinex main fEUR", "trades.mts").unwrap(),
+            Some(mts)
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, Row};
+use serde::Serialize;
+
+// Every trading decision with what it was based on. The rows of one exec step share `tick`, its
+// start time; the first of them has action `inputs` and holds the inputs as JSON. Actions are
+// `submit`, `cancel` and `skip`, with the exchange response for the former two.
+pub fn init(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS decisions (
+                    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+                    tick        DATETIME NOT NULL,
+                    mts         DATETIME NOT NULL,
+                    exchange    TEXT NOT NULL,
+                    account     TEXT NOT NULL,
+                    symbol      TEXT NOT NULL,
+                    action      TEXT NOT NULL,
+                    reason      TEXT NOT NULL,
+                    amount      REAL,
+                    rate        REAL,
+                    period      INTEGER,
+                    offer_id    INTEGER,
+                    status      TEXT,
+                    text        TEXT,
+                    inputs      TEXT
+                )",
+        params![],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS decisions_symbol_mts ON decisions (symbol, mts)",
+        params![],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Decision {
+    pub id: i64,
+    pub tick: DateTime<Utc>,
+    pub mts: DateTime<Utc>,
+    pub exchange: String,
+    pub account: String,
+    pub symbol: String,
+    pub action: String,
+    pub reason: String,
+    pub amount: Option<f64>,
+    pub rate: Option<f64>,
+    pub period: Option<u32>,
+    pub offer_id: Option<u32>,
+    // exchange response, `status` is `ERROR` if the request failed and `REJECTED` if the risk
+    // limits refused it
+    pub status: Option<String>,
+    pub text: Option<String>,
+    pub inputs: Option<String>,
+}
+
+impl Decision {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get("id")?,
+            tick: row.get("tick")?,
+            mts: row.get("mts")?,
+            exchange: row.get("exchange")?,
+            account: row.get("account")?,
+            symbol: row.get("symbol")?,
+            action: row.get("action")?,
+            reason: row.get("reason")?,
+            amount: row.get("amount")?,
+            rate: row.get("rate")?,
+            period: row.get("period")?,
+            offer_id: row.get("offer_id")?,
+            status: row.get("status")?,
+            text: row.get("text")?,
+            inputs: row.get("inputs")?,
+        })
+    }
+}
+
+pub fn insert(conn: &Connection, decision: &Decision) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT INTO decisions (
+            tick, mts, exchange, account, symbol, action, reason, amount, rate, period, offer_id,
+            status, text, inputs
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14)",
+    )?
+    .execute(params![
+        decision.tick,
+        decision.mts,
+        decision.exchange,
+        decision.account,
+        decision.symbol,
+        decision.action,
+        decision.reason,
+        decision.amount,
+        decision.rate,
+        decision.period,
+        decision.offer_id,
+        decision.status,
+        decision.text,
+        decision.inputs,
+    ])
+    .map_err(|err| anyhow!("failed to record decision: {:?}", err))?;
+
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct Filter {
+    pub exchange: Option<String>,
+    pub account: Option<String>,
+    pub symbol: Option<String>,
+    pub action: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: usize,
+}
+
+// Matching decisions, newest first
+pub fn query(conn: &Connection, filter: &Filter) -> Result<Vec<Decision>> {
+    super::query_newest(
+        conn,
+        "decisions",
+        &[
+            ("exchange", &filter.exchange),
+            ("account", &filter.account),
+            ("symbol", &filter.symbol),
+            ("action", &filter.action),
+        ],
+        filter.since,
+        filter.limit,
+        Decision::from_row,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{insert, query, Decision, Filter};
+    use chrono::{Duration, TimeZone, Utc};
+    use rusqlite::Connection;
+
+    #[test]
+    fn record_and_query() {
+        let conn = Connection::open_in_memory().unwrap();
+        super::init(&conn).unwrap();
+
+        let tick = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let decision = Decision {
+            tick,
+            mts: tick + Duration::seconds(1),
+            exchange: "Bitfinex".into(),
+            account: "main".into(),
+            symbol: "fUSD".into(),
+            action: "submit".into(),
+            reason: "test".into(),
+            amount: Some(200.),
+            rate: Some(0.0004),
+            period: Some(2),
+            offer_id: Some(42),
+            status: Some("SUCCESS".into()),
+            text: Some("Submitting funding offer".into()),
+            inputs: Some("{}".into()),
+            ..Default::default()
+        };
+        insert(&conn, &decision).unwrap();
+
+        let stored = query(
+            &conn,
+            &Filter {
+                action: Some("submit".into()),
+                limit: 10,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            format!("{:?}", stored),
+            format!("{:?}", vec![Decision { id: 1, ..decision }])
+        );
+    }
+}
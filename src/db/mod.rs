@@ -0,0 +1,286 @@
+pub mod candles;
+pub mod cursors;
+pub mod decisions;
+pub mod retention;
+pub mod stats;
+pub mod transfers;
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
+use rusqlite::{params, params_from_iter, types::Value, Connection, Row};
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+use std::time::Duration;
+
+pub type DbPool = Pool<SqliteConnectionManager>;
+pub type DbConn = PooledConnection<SqliteConnectionManager>;
+
+// `database = "tradebot.db"` or a `[database]` table with connection options
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub path: String,
+    pub journal_mode: Option<String>,
+    pub busy_timeout_ms: Option<u64>,
+}
+
+// accepts the plain path form besides the table
+pub fn deserialize_config<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Config>, D::Error> {
+    struct ConfigVisitor;
+
+    impl<'de> Visitor<'de> for ConfigVisitor {
+        type Value = Option<Config>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a database path or a table with connection options")
+        }
+
+        fn visit_str<E: de::Error>(self, path: &str) -> Result<Self::Value, E> {
+            Ok(Some(Config {
+                path: path.into(),
+                ..Default::default()
+            }))
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+            Config::deserialize(de::value::MapAccessDeserializer::new(map)).map(Some)
+        }
+    }
+
+    deserializer.deserialize_any(ConfigVisitor)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            path: "tradebot.db".into(),
+            journal_mode: None,
+            busy_timeout_ms: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        if self.path.trim().is_empty() {
+            errors.push(format!("{path}.path: must not be empty"));
+        }
+        if let Some(mode) = &self.journal_mode {
+            let modes = ["delete", "truncate", "persist", "memory", "wal", "off"];
+            if !modes.contains(&mode.to_lowercase().as_str()) {
+                errors.push(format!(
+                    "{path}.journal_mode: unknown mode {mode:?}, expected one of {}",
+                    modes.join(", ")
+                ));
+            }
+        }
+    }
+}
+
+pub fn get_pool(config: Option<Config>) -> Result<DbPool> {
+    let config = config.unwrap_or_default();
+    let journal_mode = config.journal_mode.unwrap_or("WAL".into());
+    let busy_timeout = Duration::from_millis(config.busy_timeout_ms.unwrap_or(5000));
+
+    let manager = SqliteConnectionManager::file(config.path).with_init(move |conn| {
+        conn.pragma_update(None, "journal_mode", &journal_mode)?;
+        conn.busy_timeout(busy_timeout)?;
+        rusqlite::vtab::array::load_module(conn)
+    });
+    let pool = r2d2::Pool::new(manager)?;
+
+    let conn = pool.get()?;
+
+    init(&conn)?;
+
+    Ok(pool)
+}
+
+pub fn init(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS trades (
+                    symbol  TEXT NOT NULL,
+                    mts     DATETIME NOT NULL,
+                    amount  REAL NOT NULL,
+                    rate    REAL NOT NULL,
+                    period  INTEGER NOT NULL,
+                    id      INTEGER
+                )",
+        params![],
+    )?;
+
+    add_column(conn, "trades", "id", "INTEGER")?;
+
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS trades_symbol_mts ON trades (symbol, mts)",
+        params![],
+    )?;
+
+    conn.execute(
+        "CREATE UNIQUE INDEX IF NOT EXISTS trades_symbol_id ON trades (symbol, id)",
+        params![],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS credits (
+                    id              INTEGER PRIMARY KEY,
+                    symbol          TEXT NOT NULL,
+                    amount          REAL NOT NULL,
+                    rate            REAL NOT NULL,
+                    period          INTEGER NOT NULL,
+                    opening         DATETIME NOT NULL,
+                    last_payout     DATETIME NOT NULL,
+                    position_pair   TEXT NOT NULL
+                )",
+        params![],
+    )?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS provided (
+                    id              INTEGER PRIMARY KEY,
+                    symbol          TEXT NOT NULL,
+                    \"create\"      DATETIME NOT NULL,
+                    \"update\"      DATETIME NOT NULL,
+                    amount          REAL NOT NULL,
+                    rate            REAL NOT NULL,
+                    period          INTEGER NOT NULL,
+                    position_pair   TEXT NOT NULL
+                )",
+        params![],
+    )?;
+
+    // credits, provided and offers belong to an exchange account, trades are public
+    add_column(conn, "credits", "account", "TEXT NOT NULL DEFAULT 'main'")?;
+    add_column(conn, "provided", "account", "TEXT NOT NULL DEFAULT 'main'")?;
+
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS offers (
+                    id              INTEGER PRIMARY KEY,
+                    account         TEXT NOT NULL,
+                    symbol          TEXT NOT NULL,
+                    amount          REAL NOT NULL,
+                    rate            REAL NOT NULL,
+                    period          INTEGER NOT NULL,
+                    created         DATETIME NOT NULL,
+                    last_seen       DATETIME NOT NULL
+                )",
+        params![],
+    )?;
+
+    candles::init(conn)?;
+    cursors::init(conn)?;
+    decisions::init(conn)?;
+    transfers::init(conn)?;
+
+    Ok(())
+}
+
+// Moves the write-ahead log into the database file, e.g. before exiting.
+pub fn checkpoint(conn: &Connection) -> Result<()> {
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", params![], |_| Ok(()))?;
+
+    Ok(())
+}
+
+// Rows of `table` whose `columns` equal the given values ignoring case, those left None match
+// anything, and whose `mts` is `since` or later, newest first
+fn query_newest<T>(
+    conn: &Connection,
+    table: &str,
+    columns: &[(&str, &Option<String>)],
+    since: Option<DateTime<Utc>>,
+    limit: usize,
+    from_row: fn(&Row) -> rusqlite::Result<T>,
+) -> Result<Vec<T>> {
+    let mut conditions = vec![];
+    let mut values: Vec<Value> = vec![];
+    for (column, value) in columns {
+        if let Some(value) = value {
+            conditions.push(format!("{column} = ? COLLATE NOCASE"));
+            values.push(Value::Text(value.clone()));
+        }
+    }
+    if let Some(since) = since {
+        conditions.push("mts >= DATETIME(?)".into());
+        values.push(Value::Text(since.to_rfc3339()));
+    }
+    let sql = format!(
+        "SELECT * FROM {table} {} ORDER BY mts DESC, id DESC LIMIT {limit}",
+        if conditions.is_empty() {
+            String::new()
+        } else {
+            format!("WHERE {}", conditions.join(" AND "))
+        },
+    );
+
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt
+        .query_map(params_from_iter(values), from_row)?
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .map_err(|err| anyhow!("failed to query {}: {:?}", table, err))?;
+
+    Ok(rows)
+}
+
+// Add a column to a table created by an older version, keeping existing rows.
+fn add_column(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+    let exists: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = ?2",
+        params![table, column],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        conn.execute(
+            &format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"),
+            params![],
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::query_newest;
+    use chrono::{Duration, Utc};
+    use rusqlite::{params, Connection};
+
+    #[test]
+    fn query_newest_matching() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute(
+            "CREATE TABLE events (id INTEGER PRIMARY KEY, mts DATETIME NOT NULL, symbol TEXT)",
+            params![],
+        )
+        .unwrap();
+        let now = Utc::now();
+        for (minutes, symbol) in [(10, "fUSD"), (5, "fUSD"), (5, "fEUR"), (1, "fUSD")] {
+            conn.execute(
+                "INSERT INTO events (mts, symbol) VALUES (?1, ?2)",
+                params![now - Duration::minutes(minutes), symbol],
+            )
+            .unwrap();
+        }
+        let ids = |symbol: Option<&str>, minutes: Option<i64>, limit| {
+            query_newest(
+                &conn,
+                "events",
+                &[("symbol", &symbol.map(String::from))],
+                minutes.map(|m| now - Duration::minutes(m)),
+                limit,
+                |row| row.get::<_, i64>("id"),
+            )
+            .unwrap()
+        };
+
+        assert_eq!(ids(None, None, 10), vec![4, 3, 2, 1]);
+        assert_eq!(ids(None, None, 2), vec![4, 3]);
+        assert_eq!(ids(Some("fusd"), None, 10), vec![4, 2, 1]);
+        assert_eq!(ids(Some("FUSD"), Some(6), 10), vec![4, 2]);
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+
+use super::candles::Timeframe;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub trades_days: Option<u32>,
+    pub minute_candles_days: Option<u32>,
+    pub decisions_days: Option<u32>,
+    pub interval_hours: Option<u64>,
+    pub vacuum: Option<bool>,
+}
+
+impl Config {
+    pub fn interval_hours(&self) -> u64 {
+        self.interval_hours.unwrap_or(24)
+    }
+
+    pub fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        for (key, days) in [
+            ("trades_days", self.trades_days),
+            ("minute_candles_days", self.minute_candles_days),
+            ("decisions_days", self.decisions_days),
+        ] {
+            if days == Some(0) {
+                errors.push(format!("{path}.{key}: must be at least 1"));
+            }
+        }
+        if self.interval_hours == Some(0) {
+            errors.push(format!("{path}.interval_hours: must be at least 1"));
+        }
+    }
+}
+
+pub fn prune(conn: &Connection, config: &Config) -> Result<()> {
+    // raw trades are rolled into candles by the insert trigger, so only the rows are dropped here
+    if let Some(days) = config.trades_days {
+        let count = conn
+            .execute(
+                "DELETE FROM trades WHERE mts < DATETIME('now', ?1)",
+                params![format!("-{days} days")],
+            )
+            .map_err(|err| anyhow!("failed to prune trades: {:?}", err))?;
+        info!("pruned {} trades older than {} days", count, days);
+    }
+
+    if let Some(days) = config.minute_candles_days {
+        let count = conn
+            .execute(
+                "DELETE FROM candles WHERE timeframe = ?1 AND mts < DATETIME('now', ?2)",
+                params![Timeframe::Minute.as_str(), format!("-{days} days")],
+            )
+            .map_err(|err| anyhow!("failed to prune candles: {:?}", err))?;
+        info!("pruned {} 1m candles older than {} days", count, days);
+    }
+
+    if let Some(days) = config.decisions_days {
+        let count = conn
+            .execute(
+                "DELETE FROM decisions WHERE mts < DATETIME('now', ?1)",
+                params![format!("-{days} days")],
+            )
+            .map_err(|err| anyhow!("failed to prune decisions: {:?}", err))?;
+        info!("pruned {} decisions older than {} days", count, days);
+    }
+
+    if config.vacuum.unwrap_or(true) {
+        conn.execute_batch("VACUUM")
+            .map_err(|err| anyhow!("failed to vacuum database: {:?}", err))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{prune, Config};
+    use chrono::{Duration, Utc};
+    use rusqlite::{params, Connection};
+
+    #[test]
+    fn prune_old_trades_keep_candles() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init(&conn).unwrap();
+
+        for days in [1, 10, 40] {
+            conn.execute(
+                "INSERT INTO trades (symbol, mts, amount, rate, period) VALUES ('ffUSD', ?1, 100, 0.0002, 2)",
+                params![Utc::now() - Duration::days(days)],
+            )
+            .unwrap();
+        }
+
+        let config = Config {
+            trades_days: Some(30),
+            minute_candles_days: Some(5),
+            decisions_days: None,
+            interval_hours: None,
+            vacuum: Some(false),
+        };
+        prune(&conn, &config).unwrap();
+
+        let count =
+            |sql: &str| -> u32 { conn.query_row(sql, params![], |row| row.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM trades"), 2);
+        assert_eq!(
+            count("SELECT COUNT(*) FROM candles WHERE timeframe = '1m'"),
+            1
+        );
+        assert_eq!(
+            count("SELECT SUM(count) FROM candles WHERE timeframe = '1d'"),
+            3
+        );
+    }
+}
@@ -0,0 +1,89 @@
+use anyhow::Result;
+use rusqlite::{params, Connection};
+
+pub struct TableStats {
+    pub name: String,
+    pub rows: u64,
+    pub size: u64,
+    pub symbols: Vec<(String, u64)>,
+}
+
+pub fn stats(conn: &Connection) -> Result<Vec<TableStats>> {
+    let tables: Vec<(String, u64)> = conn
+        .prepare(
+            "SELECT m.name, COALESCE(SUM(s.pgsize), 0)
+            FROM sqlite_master m
+            LEFT JOIN sqlite_master i ON i.tbl_name = m.name
+            LEFT JOIN dbstat s ON s.name = i.name
+            WHERE m.type = 'table' AND m.name NOT LIKE 'sqlite_%'
+            GROUP BY m.name
+            ORDER BY m.name",
+        )?
+        .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+        .collect::<rusqlite::Result<_>>()?;
+
+    let mut stats = vec![];
+    for (name, size) in tables {
+        let rows = conn.query_row(
+            &format!("SELECT COUNT(*) FROM \"{name}\""),
+            params![],
+            |row| row.get(0),
+        )?;
+
+        let has_symbol: bool = conn.query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info(?1) WHERE name = 'symbol'",
+            params![name],
+            |row| row.get(0),
+        )?;
+        let symbols = if has_symbol {
+            conn.prepare(&format!(
+                "SELECT symbol, COUNT(*) FROM \"{name}\" GROUP BY symbol ORDER BY symbol"
+            ))?
+            .query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?
+        } else {
+            vec![]
+        };
+
+        stats.push(TableStats {
+            name,
+            rows,
+            size,
+            symbols,
+        });
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stats;
+    use rusqlite::{params, Connection};
+
+    #[test]
+    fn tables_and_symbols() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init(&conn).unwrap();
+        for (symbol, id) in [("fUSD", 1), ("fUSD", 2), ("fBTC", 3)] {
+            conn.execute(
+                "INSERT INTO trades (symbol, mts, amount, rate, period, id)
+                VALUES (?1, DATETIME('now'), 100, 0.0002, 2, ?2)",
+                params![symbol, id],
+            )
+            .unwrap();
+        }
+
+        let tables = stats(&conn).unwrap();
+        let trades = tables.iter().find(|t| t.name == "trades").unwrap();
+        assert_eq!(trades.rows, 3);
+        assert!(trades.size > 0);
+        assert_eq!(
+            trades.symbols,
+            vec![("fBTC".to_string(), 1), ("fUSD".to_string(), 2)]
+        );
+        let cursors = tables.iter().find(|t| t.name == "cursors").unwrap();
+        assert_eq!(cursors.rows, 0);
+        assert!(cursors.symbols.is_empty());
+    }
+}
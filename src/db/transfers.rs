@@ -0,0 +1,155 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use rusqlite::{params, Connection, Row};
+use serde::Serialize;
+
+// Moves between the wallets of an account, with the exchange response
+pub fn init(conn: &Connection) -> Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS transfers (
+                    id          INTEGER PRIMARY KEY AUTOINCREMENT,
+                    mts         DATETIME NOT NULL,
+                    exchange    TEXT NOT NULL,
+                    account     TEXT NOT NULL,
+                    currency    TEXT NOT NULL,
+                    from_wallet TEXT NOT NULL,
+                    to_wallet   TEXT NOT NULL,
+                    amount      REAL NOT NULL,
+                    reason      TEXT NOT NULL,
+                    status      TEXT NOT NULL,
+                    text        TEXT
+                )",
+        params![],
+    )?;
+    conn.execute(
+        "CREATE INDEX IF NOT EXISTS transfers_account_mts ON transfers (account, mts)",
+        params![],
+    )?;
+
+    Ok(())
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Transfer {
+    pub id: i64,
+    pub mts: DateTime<Utc>,
+    pub exchange: String,
+    pub account: String,
+    pub currency: String,
+    pub from_wallet: String,
+    pub to_wallet: String,
+    pub amount: f64,
+    pub reason: String,
+    // `ERROR` if the request failed and `REJECTED` if the kill switch refused it
+    pub status: String,
+    pub text: Option<String>,
+}
+
+impl Transfer {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get("id")?,
+            mts: row.get("mts")?,
+            exchange: row.get("exchange")?,
+            account: row.get("account")?,
+            currency: row.get("currency")?,
+            from_wallet: row.get("from_wallet")?,
+            to_wallet: row.get("to_wallet")?,
+            amount: row.get("amount")?,
+            reason: row.get("reason")?,
+            status: row.get("status")?,
+            text: row.get("text")?,
+        })
+    }
+}
+
+pub fn insert(conn: &Connection, transfer: &Transfer) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT INTO transfers (
+            mts, exchange, account, currency, from_wallet, to_wallet, amount, reason, status,
+            text
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+    )?
+    .execute(params![
+        transfer.mts,
+        transfer.exchange,
+        transfer.account,
+        transfer.currency,
+        transfer.from_wallet,
+        transfer.to_wallet,
+        transfer.amount,
+        transfer.reason,
+        transfer.status,
+        transfer.text,
+    ])
+    .map_err(|err| anyhow!("failed to record transfer: {:?}", err))?;
+
+    Ok(())
+}
+
+#[derive(Default)]
+pub struct Filter {
+    pub exchange: Option<String>,
+    pub account: Option<String>,
+    pub currency: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub limit: usize,
+}
+
+// Matching transfers, newest first
+pub fn query(conn: &Connection, filter: &Filter) -> Result<Vec<Transfer>> {
+    super::query_newest(
+        conn,
+        "transfers",
+        &[
+            ("exchange", &filter.exchange),
+            ("account", &filter.account),
+            ("currency", &filter.currency),
+        ],
+        filter.since,
+        filter.limit,
+        Transfer::from_row,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{insert, query, Filter, Transfer};
+    use chrono::{TimeZone, Utc};
+    use rusqlite::Connection;
+
+    #[test]
+    fn record_and_query() {
+        let conn = Connection::open_in_memory().unwrap();
+        super::init(&conn).unwrap();
+
+        let transfer = Transfer {
+            mts: Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(),
+            exchange: "Bitfinex".into(),
+            account: "main".into(),
+            currency: "USD".into(),
+            from_wallet: "exchange".into(),
+            to_wallet: "funding".into(),
+            amount: 120.5,
+            reason: "sweep".into(),
+            status: "ERROR".into(),
+            text: Some("not enough balance".into()),
+            ..Default::default()
+        };
+        insert(&conn, &transfer).unwrap();
+
+        let stored = query(
+            &conn,
+            &Filter {
+                currency: Some("USD".into()),
+                limit: 10,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            format!("{:?}", stored),
+            format!("{:?}", vec![Transfer { id: 1, ..transfer }])
+        );
+    }
+}
@@ -0,0 +1,346 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use rusqlite::{params, Connection};
+use serde::Deserialize;
+use std::fmt::Write;
+use std::str::FromStr;
+
+use crate::bot::Bot;
+use crate::db::DbPool;
+use crate::exchange::Exchange;
+use crate::notify::{Kind, Notifier};
+use crate::risk::Risk;
+use crate::strategy::{Failure, Schedule};
+
+const DEFAULT_SCHEDULE: &str = "0 0 0 * * *";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Markdown,
+    Text,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "markdown" => Ok(Self::Markdown),
+            "text" => Ok(Self::Text),
+            _ => Err(anyhow!("unknown format: {s}")),
+        }
+    }
+}
+
+// `[digest]`, a summary of the last 24 hours per exchange account. It is sent to the channels
+// subscribed to `digest` and, with `path`, written to a file.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // daily at midnight UTC if omitted
+    pub schedule: Option<Schedule>,
+    pub format: Option<Format>,
+    // may contain {exchange}, {account} and {date}, e.g. `digests/{account}-{date}.md`
+    pub path: Option<String>,
+}
+
+impl Config {
+    pub fn schedule(&self) -> Schedule {
+        self.schedule.clone().unwrap_or_else(|| {
+            DEFAULT_SCHEDULE
+                .parse()
+                .expect("the default schedule is valid")
+        })
+    }
+
+    pub fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        if self.path.as_ref().is_some_and(|p| p.trim().is_empty()) {
+            errors.push(format!("{path}.path: must not be empty"));
+        }
+    }
+
+    // Sends or writes the digest of one account.
+    pub fn deliver(&self, digest: &Digest, notifier: &Notifier) -> Result<()> {
+        let text = digest.render(self.format.unwrap_or_default());
+
+        if let Some(path) = &self.path {
+            let path = path
+                .replace("{exchange}", digest.exchange)
+                .replace("{account}", &digest.account)
+                .replace("{date}", &digest.end.format("%Y-%m-%d").to_string());
+            std::fs::write(&path, &text)
+                .map_err(|err| anyhow!("failed to write digest {}: {:?}", path, err))?;
+        }
+        notifier.notify(
+            Kind::Digest,
+            &format!("{} {}", digest.exchange, digest.account),
+            format!("digest of {}", digest.end.format("%Y-%m-%d")),
+            text,
+        );
+
+        Ok(())
+    }
+}
+
+// Sends the digest of every account with strategies, run on `schedule`.
+pub async fn run(bot: &Bot, config: &Config, db_pool: DbPool) -> Result<()> {
+    let strategies = bot.strategies().await;
+    let exchanges = bot.exchanges();
+    let (config, notifier, risk) = (config.clone(), bot.notifier().clone(), bot.risk().clone());
+
+    tokio::task::spawn_blocking(move || -> Result<()> {
+        let conn = db_pool.get()?;
+        for exchange in exchanges
+            .iter()
+            .filter(|e| !e.params().strategies.is_empty())
+        {
+            let failures = strategies
+                .iter()
+                .filter(|s| s.exchange == exchange.name() && s.account == exchange.account())
+                .flat_map(|s| {
+                    s.status
+                        .failures
+                        .iter()
+                        .map(|f| (s.symbol.clone(), f.clone()))
+                })
+                .collect();
+            // one account failing does not hold back the digests of the others
+            let result = Digest::collect(exchange, &risk, &conn, failures)
+                .and_then(|digest| config.deliver(&digest, &notifier));
+            match result {
+                Ok(()) => log::info!(
+                    "digest of {} {} delivered",
+                    exchange.name(),
+                    exchange.account()
+                ),
+                Err(e) => log::error!(
+                    "failed to deliver digest of {} {}: {:?}",
+                    exchange.name(),
+                    exchange.account(),
+                    e
+                ),
+            }
+        }
+        Ok(())
+    })
+    .await?
+}
+
+#[derive(Debug, Default)]
+pub struct SymbolSummary {
+    pub symbol: String,
+    // count and amount of credits
+    pub opened: (usize, f64),
+    pub closed: (usize, f64),
+    // None if the exchange could not be reached
+    pub live: Option<Live>,
+}
+
+#[derive(Debug)]
+pub struct Live {
+    pub interest: f64,
+    // amount weighted over the credits provided now, None without any
+    pub average_rate: Option<f64>,
+    pub yield_lend: f64,
+    pub idle: f64,
+}
+
+#[derive(Debug)]
+pub struct Digest {
+    pub exchange: &'static str,
+    pub account: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub symbols: Vec<SymbolSummary>,
+    // `symbol step: message`, newest first
+    pub errors: Vec<(DateTime<Utc>, String)>,
+}
+
+impl Digest {
+    // Collects the last 24 hours of an account from the exchange and the stored credits.
+    // `failures` are the failed steps of the account's strategies, as recorded in their
+    // status.
+    pub fn collect(
+        exchange: &Exchange,
+        risk: &Risk,
+        conn: &Connection,
+        failures: Vec<(String, Failure)>,
+    ) -> Result<Self> {
+        let end = Utc::now();
+        let start = end - Duration::hours(24);
+        let client = exchange.lending_client(risk)?;
+        let mut errors: Vec<(DateTime<Utc>, String)> = failures
+            .into_iter()
+            .filter(|(_, f)| f.mts > start)
+            .map(|(symbol, f)| (f.mts, format!("{} {}: {}", symbol, f.step, f.message)))
+            .collect();
+
+        let mut symbols = vec![];
+        for config in &exchange.params().strategies {
+            let symbol = config.symbol();
+            let mut summary = SymbolSummary {
+                symbol: symbol.into(),
+                ..Default::default()
+            };
+            let count = |sql: &str| -> Result<(usize, f64)> {
+                conn.query_row(sql, params![exchange.account(), symbol, start], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })
+                .map_err(|err| anyhow!("failed to summarize credits: {:?}", err))
+            };
+            summary.opened = count(
+                "SELECT COUNT(*), IFNULL(SUM(amount), 0) FROM provided
+                WHERE account = ?1 AND symbol = ?2 AND \"create\" > ?3",
+            )?;
+            summary.closed = count(
+                "SELECT COUNT(*), IFNULL(SUM(amount), 0) FROM credits
+                WHERE account = ?1 AND symbol = ?2 AND last_payout > ?3",
+            )?;
+
+            let live = (|| -> Result<Live> {
+                let credits = client.credits(symbol)?;
+                let lent: f64 = credits.iter().map(|c| c.amount).sum();
+                Ok(Live {
+                    interest: client
+                        .payouts(symbol, start)?
+                        .iter()
+                        .map(|p| p.amount)
+                        .sum(),
+                    average_rate: (lent > 0.)
+                        .then(|| credits.iter().map(|c| c.amount * c.rate).sum::<f64>() / lent),
+                    yield_lend: client.info(symbol)?.yield_lend,
+                    idle: client.balance(symbol)?,
+                })
+            })();
+            match live {
+                Ok(live) => summary.live = Some(live),
+                Err(e) => errors.push((end, format!("{symbol} digest: {e}"))),
+            }
+            symbols.push(summary);
+        }
+        errors.sort_by_key(|(mts, _)| std::cmp::Reverse(*mts));
+
+        Ok(Self {
+            exchange: exchange.name(),
+            account: exchange.account().into(),
+            start,
+            end,
+            symbols,
+            errors,
+        })
+    }
+
+    pub fn render(&self, format: Format) -> String {
+        let markdown = format == Format::Markdown;
+        let (h1, h2, item) = if markdown {
+            ("## ", "### ", "- ")
+        } else {
+            ("", "", "  ")
+        };
+        let time = |mts: &DateTime<Utc>| mts.format("%Y-%m-%d %H:%M").to_string();
+        let pct = |rate: f64| format!("{:.4}%", rate * 100.);
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{h1}{} {}, {} to {} UTC",
+            self.exchange,
+            self.account,
+            time(&self.start),
+            time(&self.end)
+        );
+        for s in &self.symbols {
+            let _ = writeln!(out, "\n{h2}{}", s.symbol);
+            let _ = writeln!(
+                out,
+                "{item}credits opened: {} ({:.2})",
+                s.opened.0, s.opened.1
+            );
+            let _ = writeln!(
+                out,
+                "{item}credits closed: {} ({:.2})",
+                s.closed.0, s.closed.1
+            );
+            match &s.live {
+                Some(live) => {
+                    let _ = writeln!(out, "{item}interest earned: {:.2}", live.interest);
+                    let _ = writeln!(
+                        out,
+                        "{item}average rate: {} a day, market {}",
+                        live.average_rate.map(pct).unwrap_or_else(|| "none".into()),
+                        pct(live.yield_lend)
+                    );
+                    let _ = writeln!(out, "{item}idle balance: {:.2}", live.idle);
+                }
+                None => {
+                    let _ = writeln!(out, "{item}exchange unavailable, see errors");
+                }
+            }
+        }
+
+        let _ = writeln!(out, "\n{h2}Errors");
+        if self.errors.is_empty() {
+            let _ = writeln!(out, "{item}none");
+        }
+        for (mts, message) in &self.errors {
+            let message = message.lines().next().unwrap_or_default();
+            let _ = if markdown {
+                writeln!(out, "{item}{} `{}`", time(mts), message)
+            } else {
+                writeln!(out, "{item}{} {}", time(mts), message)
+            };
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Digest, Format, Live, SymbolSummary};
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn render_digest() {
+        let end = Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap();
+        let digest = Digest {
+            exchange: "Bitfinex",
+            account: "main".into(),
+            start: end - Duration::hours(24),
+            end,
+            symbols: vec![SymbolSummary {
+                symbol: "fUSD".into(),
+                opened: (2, 400.),
+                closed: (1, 200.),
+                live: Some(Live {
+                    interest: 1.234,
+                    average_rate: Some(0.00045),
+                    yield_lend: 0.0004,
+                    idle: 150.,
+                }),
+            }],
+            errors: vec![(end, "fUSD sync: timed out\ncaused by".into())],
+        };
+
+        assert_eq!(
+            digest.render(Format::Markdown),
+            "## Bitfinex main, 2024-01-01 00:00 to 2024-01-02 00:00 UTC
+
+### fUSD
+- credits opened: 2 (400.00)
+- credits closed: 1 (200.00)
+- interest earned: 1.23
+- average rate: 0.0450% a day, market 0.0400%
+- idle balance: 150.00
+
+### Errors
+- 2024-01-02 00:00 `fUSD sync: timed out`
+"
+        );
+        assert!(digest
+            .render(Format::Text)
+            .starts_with("Bitfinex main, 2024-01-01 00:00 to 2024-01-02 00:00 UTC\n\nfUSD\n  credits opened: 2 (400.00)\n"));
+    }
+}
@@ -14,6 +14,9 @@ use std::time::{SystemTime, UNIX_EPOCH};
 
 use super::deserializer::{bool_from_val, bool_from_val_option};
 use super::Client;
+use crate::exchange::RequestFailed;
+use crate::metrics;
+use crate::risk::Risk;
 
 static API_HOST: &str = "https://api.bitfinex.com/";
 
@@ -35,6 +38,21 @@ pub struct Book {
     pub amount: f64, // ask if amount > 0
 }
 
+// of a trading pair, e.g. tBTCUSD
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Ticker {
+    pub bid: f64,
+    pub bid_size: f64,
+    pub ask: f64,
+    pub ask_size: f64,
+    pub daily_change: f64,
+    pub daily_change_relative: f64,
+    pub last_price: f64,
+    pub volume: f64,
+    pub high: f64,
+    pub low: f64,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct FundingInfo {
     key: String,
@@ -141,18 +159,284 @@ pub struct FundingCredit {
     pub position_pair: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LedgerEntry {
+    pub id: u64,
+    pub currency: String,
+    pub wallet: Option<String>,
+    #[serde(with = "ts_milliseconds")]
+    pub mts: DateTime<Utc>,
+    #[serde(skip_serializing)]
+    _placeholder_1: Option<String>,
+    pub amount: f64,
+    pub balance: Option<f64>,
+    #[serde(skip_serializing)]
+    _placeholder_2: Option<String>,
+    pub description: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Wallet {
+    // exchange, margin or funding
+    pub wallet_type: String,
+    pub currency: String,
+    pub balance: f64,
+    pub unsettled_interest: f64,
+    // null until calculated
+    pub available_balance: Option<f64>,
+    #[serde(default)]
+    pub last_change: Option<String>,
+    #[serde(default)]
+    pub trade_details: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Transfer {
+    #[serde(with = "ts_milliseconds")]
+    pub mts_updated: DateTime<Utc>,
+    pub wallet_from: String,
+    pub wallet_to: String,
+    #[serde(skip_serializing)]
+    _placeholder_1: Option<String>,
+    pub currency: String,
+    pub currency_to: Option<String>,
+    #[serde(skip_serializing)]
+    _placeholder_2: Option<String>,
+    pub amount: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TransferResponse {
+    #[serde(with = "ts_milliseconds")]
+    pub mts: DateTime<Utc>,
+    pub transfer_type: String,
+    pub message_id: Option<u64>,
+    #[serde(skip_serializing)]
+    _placeholder_1: Option<String>,
+    pub transfer: Transfer,
+    pub code: Option<u64>,
+    pub status: String,
+    pub text: Option<String>,
+}
+
+// of a trading pair, e.g. tBTCUSD
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TradingBook {
+    pub price: f64,
+    pub count: u32,
+    pub amount: f64, // ask if amount < 0
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Order {
+    pub id: u64,
+    pub gid: Option<u64>,
+    pub cid: Option<u64>,
+    pub symbol: String,
+    #[serde(with = "ts_milliseconds")]
+    pub mts_create: DateTime<Utc>,
+    #[serde(with = "ts_milliseconds")]
+    pub mts_update: DateTime<Utc>,
+    // remaining, sell if amount < 0
+    pub amount: f64,
+    pub amount_orig: f64,
+    pub order_type: String,
+    pub type_prev: Option<String>,
+    #[serde(default)]
+    #[serde(with = "ts_milliseconds_option")]
+    pub mts_tif: Option<DateTime<Utc>>,
+    #[serde(skip_serializing)]
+    _placeholder_1: Option<String>,
+    pub flags: Option<u64>,
+    // e.g. ACTIVE, EXECUTED @ 107.6(-0.2), CANCELED
+    pub status: String,
+    #[serde(skip_serializing)]
+    _placeholder_2: Option<String>,
+    #[serde(skip_serializing)]
+    _placeholder_3: Option<String>,
+    pub price: f64,
+    pub price_avg: Option<f64>,
+    pub price_trailing: Option<f64>,
+    pub price_aux_limit: Option<f64>,
+    #[serde(skip_serializing)]
+    _placeholder_4: Option<String>,
+    #[serde(skip_serializing)]
+    _placeholder_5: Option<String>,
+    #[serde(skip_serializing)]
+    _placeholder_6: Option<String>,
+    #[serde(deserialize_with = "bool_from_val_option")]
+    pub notify: Option<bool>,
+    #[serde(deserialize_with = "bool_from_val")]
+    pub hidden: bool,
+    pub placed_id: Option<u64>,
+    #[serde(skip_serializing)]
+    _placeholder_7: Option<String>,
+    #[serde(skip_serializing)]
+    _placeholder_8: Option<String>,
+    pub routing: Option<String>,
+    #[serde(skip_serializing)]
+    _placeholder_9: Option<String>,
+    #[serde(skip_serializing)]
+    _placeholder_10: Option<String>,
+    pub meta: Option<Value>,
+}
+
+// `data` is the order for updates and single cancels, and a list of orders otherwise
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OrderResponse<T> {
+    #[serde(with = "ts_milliseconds")]
+    pub mts: DateTime<Utc>,
+    pub order_type: String,
+    pub message_id: Option<u64>,
+    #[serde(skip_serializing)]
+    _placeholder_1: Option<String>,
+    pub data: T,
+    pub code: Option<u64>,
+    pub status: String,
+    pub text: Option<String>,
+}
+
+// execution of an order
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OrderTrade {
+    pub id: u64,
+    pub symbol: String,
+    #[serde(with = "ts_milliseconds")]
+    pub mts: DateTime<Utc>,
+    pub order_id: u64,
+    // sell if exec_amount < 0
+    pub exec_amount: f64,
+    pub exec_price: f64,
+    #[serde(skip_serializing)]
+    _placeholder_1: Option<String>,
+    #[serde(skip_serializing)]
+    _placeholder_2: Option<String>,
+    // 1 if maker, -1 if taker
+    pub maker: i8,
+    pub fee: f64,
+    pub fee_currency: String,
+    pub cid: Option<u64>,
+}
+
+// Spot orders trade from the exchange wallet
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OrderType {
+    #[default]
+    Limit,
+    Market,
+    Stop,
+    StopLimit,
+}
+
+impl OrderType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Limit => "EXCHANGE LIMIT",
+            Self::Market => "EXCHANGE MARKET",
+            Self::Stop => "EXCHANGE STOP",
+            Self::StopLimit => "EXCHANGE STOP LIMIT",
+        }
+    }
+}
+
+// order flags
+const FLAG_HIDDEN: u32 = 64;
+const FLAG_POST_ONLY: u32 = 4096;
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct NewOrder {
+    pub order_type: OrderType,
+    pub symbol: String,
+    // buy if amount > 0, sell if amount < 0
+    pub amount: f64,
+    // the limit price, or the trigger price of stops; none for market orders
+    pub price: Option<f64>,
+    // the limit price of stop limit orders once triggered
+    pub price_aux_limit: Option<f64>,
+    // client order id, unique per day
+    pub cid: Option<u64>,
+    // cancelled instead of taking liquidity, limit orders only
+    pub post_only: bool,
+    pub hidden: bool,
+}
+
+impl NewOrder {
+    // The request body, or why the order is invalid
+    fn payload(&self) -> Result<Value> {
+        if !(self.amount.is_finite() && self.amount != 0.) {
+            return Err(anyhow!(
+                "order amount must be non-zero, got {}",
+                self.amount
+            ));
+        }
+        let needs_price = self.order_type != OrderType::Market;
+        if self.price.is_some() != needs_price {
+            return Err(anyhow!(
+                "{} orders {} a price",
+                self.order_type.as_str(),
+                if needs_price { "need" } else { "take no" }
+            ));
+        }
+        if self.price_aux_limit.is_some() != (self.order_type == OrderType::StopLimit) {
+            return Err(anyhow!("only stop limit orders take price_aux_limit"));
+        }
+        if self.post_only && self.order_type != OrderType::Limit {
+            return Err(anyhow!("only limit orders can be post-only"));
+        }
+
+        let mut flags = 0;
+        if self.post_only {
+            flags |= FLAG_POST_ONLY;
+        }
+        if self.hidden {
+            flags |= FLAG_HIDDEN;
+        }
+        let mut payload = json!({
+            "type": self.order_type.as_str(),
+            "symbol": self.symbol,
+            "amount": self.amount.to_string(),
+            "flags": flags,
+        });
+        if let Some(price) = self.price {
+            payload["price"] = price.to_string().into();
+        }
+        if let Some(price) = self.price_aux_limit {
+            payload["price_aux_limit"] = price.to_string().into();
+        }
+        if let Some(cid) = self.cid {
+            payload["cid"] = cid.into();
+        }
+        Ok(payload)
+    }
+}
+
+// changes to an active order, fields left None are kept
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OrderUpdate {
+    pub amount: Option<f64>,
+    pub price: Option<f64>,
+    pub price_aux_limit: Option<f64>,
+}
+
+// ledger category of margin funding payments
+const LEDGER_FUNDING_PAYMENT: u32 = 28;
+
 impl Client {
     pub fn trades(
         &self,
         symbol: &str,
         start: DateTime<Utc>,
         end: DateTime<Utc>,
+        limit: u32,
     ) -> Result<Vec<Trade>> {
+        // oldest first, so that a full page can be continued from its last trade
         self.get(
             &format!("v2/trades/{symbol}/hist"),
             &[
-                ("start", format!("{}", start.timestamp() * 1000)),
-                ("end", format!("{}", end.timestamp() * 1000)),
+                ("start", format!("{}", start.timestamp_millis())),
+                ("end", format!("{}", end.timestamp_millis())),
+                ("limit", format!("{limit}")),
+                ("sort", "1".into()),
             ],
         )
     }
@@ -161,10 +445,39 @@ impl Client {
         self.get(&format!("v2/book/{symbol}/P3"), &[("", "")])
     }
 
+    pub fn ticker(&self, symbol: &str) -> Result<Ticker> {
+        self.get(&format!("v2/ticker/{symbol}"), &[("", "")])
+    }
+
     pub fn funding_info(&self, symbol: &str) -> Result<FundingInfo> {
         self.post(&format!("v2/auth/r/info/funding/{symbol}"), json!({}))
     }
 
+    //
+    // Wallets
+    //
+    pub fn wallets(&self) -> Result<Vec<Wallet>> {
+        self.post("v2/auth/r/wallets", json!({}))
+    }
+
+    pub fn transfer(
+        &self,
+        from: &str,
+        to: &str,
+        currency: &str,
+        amount: f64,
+    ) -> Result<TransferResponse> {
+        self.post(
+            "v2/auth/w/transfer",
+            json!({
+                "from": from,
+                "to": to,
+                "currency": currency,
+                "amount": amount.to_string(),
+            }),
+        )
+    }
+
     //
     // Funding
     //
@@ -211,25 +524,128 @@ impl Client {
         self.post(&format!("v2/auth/r/funding/credits/{symbol}"), json!({}))
     }
 
-    pub fn funding_credit_history(&self, symbol: &str) -> Result<Vec<FundingCredit>> {
+    // newest first, filtered on the time of the last update
+    pub fn funding_credit_history(
+        &self,
+        symbol: &str,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<FundingCredit>> {
+        let mut payload = json!({ "limit": limit });
+        if let Some(start) = start {
+            payload["start"] = start.timestamp_millis().into();
+        }
+        if let Some(end) = end {
+            payload["end"] = end.timestamp_millis().into();
+        }
+        self.post(&format!("v2/auth/r/funding/credits/{symbol}/hist"), payload)
+    }
+
+    // newest first
+    pub fn funding_payments(
+        &self,
+        currency: &str,
+        start: DateTime<Utc>,
+        limit: u32,
+    ) -> Result<Vec<LedgerEntry>> {
         self.post(
-            &format!("v2/auth/r/funding/credits/{symbol}/hist"),
-            json!({}),
+            &format!("v2/auth/r/ledgers/{currency}/hist"),
+            json!({
+                "category": LEDGER_FUNDING_PAYMENT,
+                "start": start.timestamp_millis(),
+                "limit": limit,
+            }),
         )
     }
 
+    //
+    // Trading
+    //
+    // the top 25 price levels per side
+    pub fn trading_books(&self, symbol: &str) -> Result<Vec<TradingBook>> {
+        self.get(&format!("v2/book/{symbol}/P0"), &[("len", "25")])
+    }
+
+    // Order writes, behind the kill switch
+    pub fn orders<'a>(&'a self, risk: &'a Risk) -> Orders<'a> {
+        Orders { client: self, risk }
+    }
+
+    fn submit_order(&self, order: &NewOrder) -> Result<OrderResponse<Vec<Order>>> {
+        self.post("v2/auth/w/order/submit", order.payload()?)
+    }
+
+    fn update_order(&self, id: u64, update: &OrderUpdate) -> Result<OrderResponse<Order>> {
+        let mut payload = json!({ "id": id });
+        for (key, value) in [
+            ("amount", update.amount),
+            ("price", update.price),
+            ("price_aux_limit", update.price_aux_limit),
+        ] {
+            if let Some(value) = value {
+                payload[key] = value.to_string().into();
+            }
+        }
+        self.post("v2/auth/w/order/update", payload)
+    }
+
+    fn cancel_order(&self, id: u64) -> Result<OrderResponse<Order>> {
+        self.post("v2/auth/w/order/cancel", json!({ "id": id }))
+    }
+
+    // every active order of the account, of all pairs
+    fn cancel_all_orders(&self) -> Result<OrderResponse<Vec<Order>>> {
+        self.post("v2/auth/w/order/cancel/multi", json!({ "all": 1 }))
+    }
+
+    pub fn active_orders(&self, symbol: &str) -> Result<Vec<Order>> {
+        self.post(&format!("v2/auth/r/orders/{symbol}"), json!({}))
+    }
+
+    // closed and cancelled orders, newest first
+    pub fn order_history(
+        &self,
+        symbol: &str,
+        start: Option<DateTime<Utc>>,
+        end: Option<DateTime<Utc>>,
+        limit: u32,
+    ) -> Result<Vec<Order>> {
+        let mut payload = json!({ "limit": limit });
+        if let Some(start) = start {
+            payload["start"] = start.timestamp_millis().into();
+        }
+        if let Some(end) = end {
+            payload["end"] = end.timestamp_millis().into();
+        }
+        self.post(&format!("v2/auth/r/orders/{symbol}/hist"), payload)
+    }
+
+    pub fn order_trades(&self, symbol: &str, id: u64) -> Result<Vec<OrderTrade>> {
+        self.post(&format!("v2/auth/r/order/{symbol}:{id}/trades"), json!({}))
+    }
+
     fn get<P, R>(&self, path: &str, params: &P) -> Result<R>
     where
         P: Serialize + ?Sized,
         R: DeserializeOwned,
     {
-        let url = format!("{API_HOST}{path}");
-        let response = self.client.get(url).query(params).send()?;
+        self.timed(path, || {
+            let url = format!("{API_HOST}{path}");
+            let response = self.client.get(url).query(params).send()?;
 
-        self.response_body(response)
+            self.response_body(response)
+        })
     }
 
     fn post<R>(&self, path: &str, payload: Value) -> Result<R>
+    where
+        R: DeserializeOwned,
+    {
+        self.timed(path, || self.signed_post(path, payload))
+    }
+
+    fn signed_post<R>(&self, path: &str, payload: Value) -> Result<R>
     where
         R: DeserializeOwned,
     {
@@ -257,6 +673,28 @@ impl Client {
         self.response_body(response)
     }
 
+    // Records the latency of a request and counts it if it fails.
+    fn timed<R>(&self, path: &str, request: impl FnOnce() -> Result<R>) -> Result<R> {
+        let endpoint = metrics::endpoint(path);
+        let labels = ["Bitfinex", self.account.as_str(), endpoint.as_str()];
+        let _span = tracing::info_span!(
+            "request",
+            exchange = labels[0],
+            account = labels[1],
+            endpoint = labels[2]
+        )
+        .entered();
+        let timer = metrics::API_REQUEST_SECONDS
+            .with_label_values(&labels)
+            .start_timer();
+        let result = request();
+        timer.observe_duration();
+        result.map_err(|err| {
+            metrics::API_ERRORS.with_label_values(&labels).inc();
+            RequestFailed(err).into()
+        })
+    }
+
     fn response_body<R>(&self, response: Response) -> Result<R>
     where
         R: DeserializeOwned,
@@ -277,3 +715,118 @@ impl Client {
         }
     }
 }
+
+// Writes orders of a client, refusing them while the kill switch is engaged
+pub struct Orders<'a> {
+    client: &'a Client,
+    risk: &'a Risk,
+}
+
+impl Orders<'_> {
+    pub fn submit(&self, order: &NewOrder) -> Result<OrderResponse<Vec<Order>>> {
+        self.risk.check_halted()?;
+        self.client.submit_order(order)
+    }
+
+    pub fn update(&self, id: u64, update: &OrderUpdate) -> Result<OrderResponse<Order>> {
+        self.risk.check_halted()?;
+        self.client.update_order(id, update)
+    }
+
+    pub fn cancel(&self, id: u64) -> Result<OrderResponse<Order>> {
+        self.risk.check_halted()?;
+        self.client.cancel_order(id)
+    }
+
+    pub fn cancel_all(&self) -> Result<OrderResponse<Vec<Order>>> {
+        self.risk.check_halted()?;
+        self.client.cancel_all_orders()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NewOrder, Order, OrderResponse, OrderTrade, OrderType};
+    use crate::exchange::bitfinex::Client;
+    use crate::risk::{self, Rejected, Risk};
+    use secrecy::Secret;
+
+    #[test]
+    fn orders() {
+        let order = NewOrder {
+            symbol: "tBTCUSD".into(),
+            amount: -0.5,
+            price: Some(30000.),
+            cid: Some(42),
+            post_only: true,
+            ..Default::default()
+        };
+        let payload = order.payload().unwrap();
+        assert_eq!(payload["type"], "EXCHANGE LIMIT");
+        assert_eq!(payload["amount"], "-0.5");
+        assert_eq!(payload["price"], "30000");
+        assert_eq!(payload["flags"], 4096);
+        assert_eq!(payload["cid"], 42);
+        for invalid in [
+            NewOrder {
+                order_type: OrderType::Market,
+                ..order.clone()
+            },
+            NewOrder {
+                order_type: OrderType::StopLimit,
+                post_only: false,
+                ..order.clone()
+            },
+            NewOrder {
+                amount: 0.,
+                ..order.clone()
+            },
+        ] {
+            assert!(invalid.payload().is_err(), "{invalid:?}");
+        }
+
+        let response: OrderResponse<Vec<Order>> = serde_json::from_str(
+            r#"[1678988263842,"on-req",null,null,[[1747566428,null,1678987199446,"tBTCUSD",
+            1678988263842,1678988263843,-0.5,-0.5,"EXCHANGE LIMIT",null,null,null,4096,"ACTIVE",
+            null,null,30000,0,0,0,null,null,null,0,0,null,null,null,"API>BFX",null,null,{}]],
+            null,"SUCCESS","Submitting 1 orders."]"#,
+        )
+        .unwrap();
+        assert_eq!(response.status, "SUCCESS");
+        assert_eq!(response.data[0].id, 1747566428);
+        assert_eq!(response.data[0].cid, Some(1678987199446));
+        assert_eq!(response.data[0].flags, Some(4096));
+        assert_eq!(response.data[0].price, 30000.);
+        assert!(!response.data[0].hidden);
+
+        let trades: Vec<OrderTrade> = serde_json::from_str(
+            r#"[[1227152380,"tBTCUSD",1678988270000,1747566428,-0.5,30001.5,null,null,1,
+            -0.015,"USD",1678987199446]]"#,
+        )
+        .unwrap();
+        assert_eq!(trades[0].order_id, 1747566428);
+        assert_eq!(trades[0].maker, 1);
+    }
+
+    #[test]
+    fn orders_halted() {
+        let path =
+            std::env::temp_dir().join(format!("tradebot-orders-{}.halt", std::process::id()));
+        let risk = Risk::new(risk::Config {
+            kill_switch: Some(path.to_string_lossy().into()),
+            ..Default::default()
+        });
+        risk.engage("test").unwrap();
+        let client = Client {
+            account: "main".into(),
+            api_key: Secret::new("key".into()),
+            api_secret: Secret::new("secret".into()),
+            client: reqwest::blocking::Client::new(),
+        };
+
+        // refused before any request is made
+        let err = client.orders(&risk).cancel_all().unwrap_err();
+        assert!(matches!(err.downcast_ref(), Some(Rejected::Halted(_))));
+        let _ = std::fs::remove_file(&path);
+    }
+}
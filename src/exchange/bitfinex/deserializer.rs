@@ -92,10 +92,10 @@ mod tests {
         let s3: S = serde_json::from_str("{\"b\":false}").unwrap();
         let s4: S = serde_json::from_str("{\"b\":0}").unwrap();
 
-        assert_eq!(s1.b, true);
-        assert_eq!(s2.b, true);
-        assert_eq!(s3.b, false);
-        assert_eq!(s4.b, false);
+        assert!(s1.b);
+        assert!(s2.b);
+        assert!(!s3.b);
+        assert!(!s4.b);
     }
 
     #[test]
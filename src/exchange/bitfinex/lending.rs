@@ -1,20 +1,34 @@
 use anyhow::Result;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use std::convert::From;
 
-use crate::strategy::lending::{Api, Book, Credit, Info, Offer, Trade};
+use crate::strategy::lending::{
+    Api, Book, Credit, Info, Offer, OfferResponse, Payout, Trade, TransferResponse, Wallet,
+};
 
 impl From<super::FundingOffer> for Offer {
     fn from(item: super::FundingOffer) -> Self {
         Self {
             id: item.id,
             symbol: item.symbol,
+            amount: item.amount,
             rate: item.rate,
+            period: item.period,
             mts_created: item.mts_created,
         }
     }
 }
 
+impl From<super::FundingOfferResponse> for OfferResponse {
+    fn from(item: super::FundingOfferResponse) -> Self {
+        Self {
+            id: item.offer.id,
+            status: item.status,
+            text: item.text,
+        }
+    }
+}
+
 impl From<super::Book> for Book {
     fn from(item: super::Book) -> Self {
         Self {
@@ -28,6 +42,7 @@ impl From<super::Book> for Book {
 impl From<super::Trade> for Trade {
     fn from(item: super::Trade) -> Self {
         Self {
+            id: item.id,
             mts: item.mts,
             amount: item.amount,
             rate: item.rate,
@@ -62,26 +77,81 @@ impl From<super::FundingInfo> for Info {
     }
 }
 
+impl From<super::Wallet> for Wallet {
+    fn from(item: super::Wallet) -> Self {
+        Self {
+            kind: item.wallet_type,
+            currency: item.currency,
+            balance: item.balance,
+            available: item.available_balance,
+        }
+    }
+}
+
+impl From<super::TransferResponse> for TransferResponse {
+    fn from(item: super::TransferResponse) -> Self {
+        Self {
+            status: item.status,
+            text: item.text,
+        }
+    }
+}
+
+impl From<super::LedgerEntry> for Payout {
+    fn from(item: super::LedgerEntry) -> Self {
+        Self {
+            id: item.id,
+            mts: item.mts,
+            amount: item.amount,
+        }
+    }
+}
+
 impl Api for super::Client {
+    fn account(&self) -> &str {
+        &self.account
+    }
     fn info(&self, symbol: &str) -> Result<Info> {
-        Ok(self.funding_info(symbol).unwrap().into())
+        Ok(self.funding_info(symbol)?.into())
     }
     fn history(
         &self,
         symbol: &str,
         start: DateTime<Utc>,
         end: DateTime<Utc>,
+        limit: u32,
     ) -> Result<Vec<Trade>> {
-        let trades = self.trades(symbol, start, end)?;
+        let trades = self.trades(symbol, start, end, limit)?;
         Ok(trades.into_iter().map(|t| t.into()).collect())
     }
     fn credits(&self, symbol: &str) -> Result<Vec<Credit>> {
         let credits = self.funding_credits(symbol)?;
         Ok(credits.into_iter().map(|c| c.into()).collect())
     }
-    fn credit_history(&self, symbol: &str) -> Result<Vec<Credit>> {
-        let credits = self.funding_credit_history(symbol)?;
-        Ok(credits.into_iter().map(|c| c.into()).collect())
+    fn credit_history(&self, symbol: &str, since: Option<DateTime<Utc>>) -> Result<Vec<Credit>> {
+        const LIMIT: u32 = 500;
+
+        // pages come newest first, walk back until `since`
+        let mut credits = vec![];
+        let mut end = None;
+        loop {
+            let page = self.funding_credit_history(symbol, since, end, LIMIT)?;
+            let full = page.len() as u32 >= LIMIT;
+            end = page
+                .last()
+                .map(|c| c.mts_update - Duration::milliseconds(1));
+            credits.extend(page.into_iter().map(Credit::from));
+            if since.is_none() || !full {
+                break;
+            }
+        }
+        Ok(credits)
+    }
+    fn payouts(&self, symbol: &str, since: DateTime<Utc>) -> Result<Vec<Payout>> {
+        // ledgers are per currency, USD for fUSD
+        let currency = symbol.strip_prefix('f').unwrap_or(symbol);
+        let payouts = self.funding_payments(currency, since, 500)?;
+        Ok(payouts.into_iter().map(|p| p.into()).collect())
     }
     fn balance(&self, symbol: &str) -> Result<f64> {
         self.funding_balance_available(symbol)
@@ -90,16 +160,46 @@ impl Api for super::Client {
         let offers = self.active_funding_offers(symbol)?;
         Ok(offers.into_iter().map(|o| o.into()).collect())
     }
-    fn submit_offer(&self, symbol: &str, amount: f64, rate: f64, period: u32) -> Result<()> {
-        self.submit_funding_offer(symbol, amount, rate, period)?;
-        Ok(())
+    fn submit_offer(
+        &self,
+        symbol: &str,
+        amount: f64,
+        rate: f64,
+        period: u32,
+    ) -> Result<OfferResponse> {
+        Ok(self
+            .submit_funding_offer(symbol, amount, rate, period)?
+            .into())
     }
-    fn cancel_offer(&self, id: u32) -> Result<()> {
-        self.cancel_funding_offer(id)?;
-        Ok(())
+    fn cancel_offer(&self, id: u32) -> Result<OfferResponse> {
+        Ok(self.cancel_funding_offer(id)?.into())
     }
     fn books(&self, symbol: &str) -> Result<Vec<Book>> {
         let books = self.books(symbol)?;
         Ok(books.into_iter().map(|b| b.into()).collect())
     }
+    fn wallets(&self) -> Result<Vec<Wallet>> {
+        let wallets = self.wallets()?;
+        Ok(wallets.into_iter().map(|w| w.into()).collect())
+    }
+    fn transfer(
+        &self,
+        from: &str,
+        to: &str,
+        currency: &str,
+        amount: f64,
+    ) -> Result<TransferResponse> {
+        Ok(self.transfer(from, to, currency, amount)?.into())
+    }
+    fn price(&self, currency: &str, quote: &str) -> Result<f64> {
+        if currency == quote {
+            return Ok(1.);
+        }
+        // pairs with a currency code longer than three letters are separated by a colon
+        let pair = match currency.len() > 3 || quote.len() > 3 {
+            true => format!("t{currency}:{quote}"),
+            false => format!("t{currency}{quote}"),
+        };
+        Ok(self.ticker(&pair)?.last_price)
+    }
 }
@@ -1,4 +1,4 @@
-mod api;
+pub(crate) mod api;
 mod deserializer;
 mod lending;
 
@@ -8,6 +8,7 @@ use api::*;
 
 #[derive(Clone, Debug)]
 pub struct Client {
+    pub account: String,
     pub api_key: Secret<String>,
     pub api_secret: Secret<String>,
     pub client: reqwest::blocking::Client,
@@ -16,6 +17,9 @@ pub struct Client {
 impl From<super::Params> for Client {
     fn from(item: super::Params) -> Self {
         Self {
+            account: item
+                .account
+                .unwrap_or_else(|| super::DEFAULT_ACCOUNT.into()),
             api_key: item.api_key,
             api_secret: item.api_secret,
             client: reqwest::blocking::Client::new(),
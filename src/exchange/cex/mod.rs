@@ -1,11 +1,15 @@
 #[derive(Clone, Debug)]
 pub struct Client {
+    pub account: String,
     pub client: reqwest::blocking::Client,
 }
 
 impl From<super::Params> for Client {
-    fn from(_item: super::Params) -> Self {
+    fn from(item: super::Params) -> Self {
         Self {
+            account: item
+                .account
+                .unwrap_or_else(|| super::DEFAULT_ACCOUNT.into()),
             client: reqwest::blocking::Client::new(),
         }
     }
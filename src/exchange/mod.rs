@@ -1,34 +1,134 @@
-mod bitfinex;
+pub(crate) mod bitfinex;
 mod cex;
 
+use crate::config;
 use crate::db::DbPool;
+use crate::notify::Notifier;
+use crate::risk::Risk;
 use crate::strategy::{self, lending, Strategy};
 use anyhow::{anyhow, Result};
-use secrecy::Secret;
-use serde::Deserialize;
+use secrecy::{ExposeSecret, Secret};
+use serde::{de, Deserialize, Deserializer};
 use std::sync::Arc;
 
+pub const DEFAULT_ACCOUNT: &str = "main";
+
+// A request the exchange failed or did not answer, as opposed to a local failure
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct RequestFailed(pub anyhow::Error);
+
 #[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Params {
+    // distinguishes sub-accounts of the same exchange, `main` if omitted
+    pub account: Option<String>,
     pub api_key: Secret<String>,
     pub api_secret: Secret<String>,
     pub strategies: Vec<strategy::Config>,
+    // cancel all open offers of the configured strategies on shutdown
+    pub cancel_on_exit: Option<bool>,
 }
 
-#[derive(Clone, Debug, Deserialize)]
-#[serde(tag = "name")]
+impl Params {
+    // whether clients built from either act with the same API key
+    pub fn same_credentials(&self, other: &Params) -> bool {
+        self.api_key.expose_secret() == other.api_key.expose_secret()
+            && self.api_secret.expose_secret() == other.api_secret.expose_secret()
+    }
+}
+
+#[derive(Clone, Debug)]
 pub enum Exchange {
     Cex(Params),
     Bitfinex(Params),
 }
 
+impl<'de> Deserialize<'de> for Exchange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (name, fields) = config::tagged(deserializer)?;
+        match name.as_str() {
+            "Cex" => Ok(Self::Cex(config::variant(fields)?)),
+            "Bitfinex" => Ok(Self::Bitfinex(config::variant(fields)?)),
+            _ => Err(de::Error::unknown_variant(&name, &["Cex", "Bitfinex"])),
+        }
+    }
+}
+
 impl Exchange {
-    fn get_strategies(self) -> Vec<strategy::Config> {
+    pub fn name(&self) -> &'static str {
         match self {
-            Self::Cex(params) => params.strategies,
-            Self::Bitfinex(params) => params.strategies,
+            Self::Cex(_) => "Cex",
+            Self::Bitfinex(_) => "Bitfinex",
         }
     }
+
+    pub fn params(&self) -> &Params {
+        match self {
+            Self::Cex(params) => params,
+            Self::Bitfinex(params) => params,
+        }
+    }
+
+    pub fn account(&self) -> &str {
+        self.params().account.as_deref().unwrap_or(DEFAULT_ACCOUNT)
+    }
+
+    pub fn params_mut(&mut self) -> &mut Params {
+        match self {
+            Self::Cex(params) => params,
+            Self::Bitfinex(params) => params,
+        }
+    }
+
+    pub fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        let params = self.params();
+        if !self
+            .account()
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+            || self.account().is_empty()
+        {
+            errors.push(format!(
+                "{path}.account: {:?} may only contain letters, digits, '_' and '-'",
+                self.account()
+            ));
+        }
+        if params.api_key.expose_secret().is_empty() {
+            errors.push(format!("{path}.api_key: must not be empty"));
+        }
+        if params.api_secret.expose_secret().is_empty() {
+            errors.push(format!("{path}.api_secret: must not be empty"));
+        }
+
+        for (idx, config) in params.strategies.iter().enumerate() {
+            let path = format!("{path}.strategies[{idx}]");
+            if matches!(self, Self::Cex(_)) {
+                errors.push(format!(
+                    "{path}: lending is not supported on {}",
+                    self.name()
+                ));
+            }
+            if params.strategies[..idx]
+                .iter()
+                .any(|other| other.symbol() == config.symbol())
+            {
+                errors.push(format!(
+                    "{path}: {} is already configured for this exchange",
+                    config.symbol()
+                ));
+            }
+            config.validate(&path, errors);
+        }
+    }
+
+    pub fn strategy_id(&self, config: &strategy::Config) -> String {
+        format!("{} {} {}", self.name(), self.account(), config.symbol())
+    }
+
+    pub fn lending_client(&self, risk: &Risk) -> Result<Arc<dyn lending::Api>> {
+        ExchangeApiClient::from(self.clone()).lending(risk)
+    }
 }
 
 pub enum ExchangeApiClient {
@@ -36,6 +136,30 @@ pub enum ExchangeApiClient {
     Bitfinex(Arc<bitfinex::Client>),
 }
 
+impl ExchangeApiClient {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Cex(_) => "Cex",
+            Self::Bitfinex(_) => "Bitfinex",
+        }
+    }
+
+    pub fn account(&self) -> &str {
+        match self {
+            Self::Cex(client) => &client.account,
+            Self::Bitfinex(client) => &client.account,
+        }
+    }
+
+    // Writes are checked against the limits and the kill switch of `risk`.
+    pub fn lending(&self, risk: &Risk) -> Result<Arc<dyn lending::Api>> {
+        match self {
+            Self::Cex(_) => Err(anyhow!("lending is not supported on Cex")),
+            Self::Bitfinex(client) => Ok(risk.guard(self.name(), client.clone())),
+        }
+    }
+}
+
 impl From<Exchange> for ExchangeApiClient {
     fn from(config: Exchange) -> Self {
         match config {
@@ -45,21 +169,56 @@ impl From<Exchange> for ExchangeApiClient {
     }
 }
 
+// Exchange clients are blocking, so none of these must be called from an async context.
 impl Exchange {
-    pub async fn exec(&self, db_pool: DbPool) -> Result<()> {
+    pub fn strategies(
+        &self,
+        db_pool: DbPool,
+        notifier: &Notifier,
+        risk: &Risk,
+    ) -> Result<Vec<Box<dyn Strategy + Send>>> {
         let client: Arc<ExchangeApiClient> = Arc::new(self.clone().into());
-        let strategy_configs = self.clone().get_strategies();
-
-        for config in strategy_configs {
-            let client = client.clone();
-            let db_pool = db_pool.clone();
-            match config {
-                strategy::Config::Lending(config) => {
-                    lending::Strategy::new(client, db_pool, config)
-                        .exec()
-                        .map_err(|e| anyhow!("[{:?}]: {:?}", self, e))?
-                }
-            };
+
+        self.params()
+            .strategies
+            .iter()
+            .map(|config| {
+                config.build(
+                    self.strategy_id(config),
+                    client.clone(),
+                    db_pool.clone(),
+                    notifier.clone(),
+                    risk,
+                )
+            })
+            .collect()
+    }
+
+    // Builds one of the configured strategies with a client of its own.
+    pub fn strategy(
+        &self,
+        config: &strategy::Config,
+        db_pool: DbPool,
+        notifier: &Notifier,
+        risk: &Risk,
+    ) -> Result<Box<dyn Strategy + Send>> {
+        config.build(
+            self.strategy_id(config),
+            Arc::new(self.clone().into()),
+            db_pool,
+            notifier.clone(),
+            risk,
+        )
+    }
+
+    pub fn tick(&self, db_pool: DbPool, risk: &Risk) -> Result<()> {
+        let _span =
+            tracing::info_span!("tick", exchange = self.name(), account = self.account()).entered();
+        for mut strategy in self.strategies(db_pool, &Notifier::disabled(), risk)? {
+            strategy
+                .sync()
+                .and_then(|_| strategy.exec())
+                .map_err(|e| anyhow!("[{}]: {:?}", self.name(), e))?;
         }
 
         Ok(())
@@ -0,0 +1,430 @@
+use anyhow::{anyhow, Result};
+use arrow_array::builder::{
+    Float64Builder, Int64Builder, StringBuilder, TimestampMillisecondBuilder,
+};
+use arrow_array::{ArrayRef, RecordBatch};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use chrono::{DateTime, Utc};
+use parquet::arrow::ArrowWriter;
+use rusqlite::{params_from_iter, types::Value, Connection, Row};
+use serde_json::json;
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const BATCH_SIZE: usize = 8192;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Table {
+    Trades,
+    Credits,
+    Provided,
+    Offers,
+    Candles,
+    Decisions,
+}
+
+impl Table {
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Trades => "trades",
+            Self::Credits => "credits",
+            Self::Provided => "provided",
+            Self::Offers => "offers",
+            Self::Candles => "candles",
+            Self::Decisions => "decisions",
+        }
+    }
+
+    // market data is shared by all accounts
+    fn has_account(&self) -> bool {
+        matches!(
+            self,
+            Self::Credits | Self::Provided | Self::Offers | Self::Decisions
+        )
+    }
+
+    // trades and candles are stored under the symbol of the funding book, as import does
+    fn stored_symbol(&self, symbol: &str) -> String {
+        match self {
+            Self::Trades | Self::Candles => format!("f{symbol}"),
+            _ => symbol.to_string(),
+        }
+    }
+
+    fn time_column(&self) -> &'static str {
+        match self {
+            Self::Trades | Self::Candles | Self::Decisions => "mts",
+            Self::Credits => "opening",
+            Self::Provided => "\"create\"",
+            Self::Offers => "created",
+        }
+    }
+}
+
+impl FromStr for Table {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "trades" => Ok(Self::Trades),
+            "credits" => Ok(Self::Credits),
+            "provided" => Ok(Self::Provided),
+            "offers" => Ok(Self::Offers),
+            "candles" => Ok(Self::Candles),
+            "decisions" => Ok(Self::Decisions),
+            _ => Err(anyhow!("unknown table: {s}")),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Format {
+    Csv,
+    Jsonl,
+    Parquet,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "jsonl" => Ok(Self::Jsonl),
+            "parquet" => Ok(Self::Parquet),
+            _ => Err(anyhow!("unknown format: {s}")),
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct Filter {
+    pub symbols: Vec<String>,
+    pub accounts: Vec<String>,
+    pub start: Option<DateTime<Utc>>,
+    pub end: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Integer,
+    Real,
+    Text,
+    Datetime,
+}
+
+impl Kind {
+    fn from_decl_type(decl_type: Option<&str>) -> Self {
+        match decl_type.map(|t| t.to_uppercase()).as_deref() {
+            Some("INTEGER") => Self::Integer,
+            Some("REAL") => Self::Real,
+            Some("DATETIME") => Self::Datetime,
+            _ => Self::Text,
+        }
+    }
+}
+
+trait RowWriter {
+    fn write(&mut self, row: &Row) -> Result<()>;
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+// Stream the rows of `table` matching `filter` to `out`, returning the number of rows written.
+pub fn export<W>(
+    conn: &Connection,
+    table: Table,
+    filter: &Filter,
+    format: Format,
+    out: W,
+) -> Result<u64>
+where
+    W: Write + Send,
+{
+    let time_column = table.time_column();
+    let mut conditions = vec![];
+    let mut params: Vec<Value> = vec![];
+
+    if !filter.symbols.is_empty() {
+        conditions.push(format!(
+            "symbol IN ({})",
+            vec!["?"; filter.symbols.len()].join(", ")
+        ));
+        params.extend(
+            filter
+                .symbols
+                .iter()
+                .map(|symbol| Value::Text(table.stored_symbol(symbol))),
+        );
+    }
+    if !filter.accounts.is_empty() {
+        if !table.has_account() {
+            return Err(anyhow!("{} are not stored per account", table.name()));
+        }
+        conditions.push(format!(
+            "account IN ({})",
+            vec!["?"; filter.accounts.len()].join(", ")
+        ));
+        params.extend(filter.accounts.iter().cloned().map(Value::Text));
+    }
+    if let Some(start) = filter.start {
+        conditions.push(format!("{time_column} >= DATETIME(?)"));
+        params.push(Value::Text(start.to_rfc3339()));
+    }
+    if let Some(end) = filter.end {
+        conditions.push(format!("{time_column} < DATETIME(?)"));
+        params.push(Value::Text(end.to_rfc3339()));
+    }
+
+    let mut sql = format!("SELECT * FROM {}", table.name());
+    if !conditions.is_empty() {
+        sql += &format!(" WHERE {}", conditions.join(" AND "));
+    }
+    sql += &format!(" ORDER BY {time_column}");
+
+    let mut stmt = conn.prepare(&sql)?;
+    let columns: Vec<(String, Kind)> = stmt
+        .columns()
+        .iter()
+        .map(|c| (c.name().to_string(), Kind::from_decl_type(c.decl_type())))
+        .collect();
+
+    let mut writer: Box<dyn RowWriter + '_> = match format {
+        Format::Csv => Box::new(CsvWriter::new(out, &columns)?),
+        Format::Jsonl => Box::new(JsonlWriter::new(out, &columns)),
+        Format::Parquet => Box::new(ParquetWriter::new(out, &columns)?),
+    };
+
+    let mut count = 0;
+    let mut rows = stmt.query(params_from_iter(params))?;
+    while let Some(row) = rows.next()? {
+        writer
+            .write(row)
+            .map_err(|err| anyhow!("failed to export row {}: {:?}", count, err))?;
+        count += 1;
+    }
+    writer.finish()?;
+
+    Ok(count)
+}
+
+fn get_datetime(row: &Row, idx: usize) -> Result<Option<DateTime<Utc>>> {
+    Ok(row.get(idx)?)
+}
+
+struct CsvWriter<W: Write> {
+    writer: csv::Writer<W>,
+    columns: Vec<Kind>,
+}
+
+impl<W: Write> CsvWriter<W> {
+    fn new(out: W, columns: &[(String, Kind)]) -> Result<Self> {
+        let mut writer = csv::Writer::from_writer(out);
+        writer.write_record(columns.iter().map(|(name, _)| name))?;
+        Ok(Self {
+            writer,
+            columns: columns.iter().map(|(_, kind)| *kind).collect(),
+        })
+    }
+}
+
+impl<W: Write> RowWriter for CsvWriter<W> {
+    fn write(&mut self, row: &Row) -> Result<()> {
+        let mut record = Vec::with_capacity(self.columns.len());
+        for (idx, kind) in self.columns.iter().enumerate() {
+            record.push(match kind {
+                Kind::Integer => row.get::<_, Option<i64>>(idx)?.map(|v| v.to_string()),
+                Kind::Real => row.get::<_, Option<f64>>(idx)?.map(|v| v.to_string()),
+                Kind::Text => row.get::<_, Option<String>>(idx)?,
+                Kind::Datetime => get_datetime(row, idx)?.map(|v| v.to_rfc3339()),
+            });
+        }
+        self.writer
+            .write_record(record.iter().map(|v| v.as_deref().unwrap_or("")))?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+struct JsonlWriter<W: Write> {
+    out: W,
+    columns: Vec<(String, Kind)>,
+}
+
+impl<W: Write> JsonlWriter<W> {
+    fn new(out: W, columns: &[(String, Kind)]) -> Self {
+        Self {
+            out,
+            columns: columns.to_vec(),
+        }
+    }
+}
+
+impl<W: Write> RowWriter for JsonlWriter<W> {
+    fn write(&mut self, row: &Row) -> Result<()> {
+        let mut object = serde_json::Map::new();
+        for (idx, (name, kind)) in self.columns.iter().enumerate() {
+            let value = match kind {
+                Kind::Integer => json!(row.get::<_, Option<i64>>(idx)?),
+                Kind::Real => json!(row.get::<_, Option<f64>>(idx)?),
+                Kind::Text => json!(row.get::<_, Option<String>>(idx)?),
+                Kind::Datetime => json!(get_datetime(row, idx)?.map(|v| v.to_rfc3339())),
+            };
+            object.insert(name.clone(), value);
+        }
+        serde_json::to_writer(&mut self.out, &object)?;
+        self.out.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+enum Builder {
+    Integer(Int64Builder),
+    Real(Float64Builder),
+    Text(StringBuilder),
+    Datetime(TimestampMillisecondBuilder),
+}
+
+impl Builder {
+    fn new(kind: Kind) -> Self {
+        match kind {
+            Kind::Integer => Self::Integer(Int64Builder::with_capacity(BATCH_SIZE)),
+            Kind::Real => Self::Real(Float64Builder::with_capacity(BATCH_SIZE)),
+            Kind::Text => Self::Text(StringBuilder::new()),
+            Kind::Datetime => Self::Datetime(
+                TimestampMillisecondBuilder::with_capacity(BATCH_SIZE).with_timezone("UTC"),
+            ),
+        }
+    }
+
+    fn data_type(kind: Kind) -> DataType {
+        match kind {
+            Kind::Integer => DataType::Int64,
+            Kind::Real => DataType::Float64,
+            Kind::Text => DataType::Utf8,
+            Kind::Datetime => DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+        }
+    }
+
+    fn append(&mut self, row: &Row, idx: usize) -> Result<()> {
+        match self {
+            Self::Integer(b) => b.append_option(row.get::<_, Option<i64>>(idx)?),
+            Self::Real(b) => b.append_option(row.get::<_, Option<f64>>(idx)?),
+            Self::Text(b) => b.append_option(row.get::<_, Option<String>>(idx)?),
+            Self::Datetime(b) => {
+                b.append_option(get_datetime(row, idx)?.map(|v| v.timestamp_millis()))
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> ArrayRef {
+        match self {
+            Self::Integer(b) => Arc::new(b.finish()),
+            Self::Real(b) => Arc::new(b.finish()),
+            Self::Text(b) => Arc::new(b.finish()),
+            Self::Datetime(b) => Arc::new(b.finish()),
+        }
+    }
+}
+
+struct ParquetWriter<W: Write + Send> {
+    writer: ArrowWriter<W>,
+    schema: Arc<Schema>,
+    builders: Vec<Builder>,
+    rows: usize,
+}
+
+impl<W: Write + Send> ParquetWriter<W> {
+    fn new(out: W, columns: &[(String, Kind)]) -> Result<Self> {
+        let schema = Arc::new(Schema::new(
+            columns
+                .iter()
+                .map(|(name, kind)| Field::new(name, Builder::data_type(*kind), true))
+                .collect::<Vec<_>>(),
+        ));
+        Ok(Self {
+            writer: ArrowWriter::try_new(out, schema.clone(), None)?,
+            schema,
+            builders: columns
+                .iter()
+                .map(|(_, kind)| Builder::new(*kind))
+                .collect(),
+            rows: 0,
+        })
+    }
+
+    fn flush_batch(&mut self) -> Result<()> {
+        if self.rows > 0 {
+            let arrays = self.builders.iter_mut().map(|b| b.finish()).collect();
+            let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+            self.writer.write(&batch)?;
+            self.rows = 0;
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write + Send> RowWriter for ParquetWriter<W> {
+    fn write(&mut self, row: &Row) -> Result<()> {
+        for (idx, builder) in self.builders.iter_mut().enumerate() {
+            builder.append(row, idx)?;
+        }
+        self.rows += 1;
+        if self.rows >= BATCH_SIZE {
+            self.flush_batch()?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        self.flush_batch()?;
+        self.writer.close()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export, Filter, Format, Table};
+    use chrono::{TimeZone, Utc};
+    use rusqlite::{params, Connection};
+
+    #[test]
+    fn export_filtered_csv() {
+        let conn = Connection::open_in_memory().unwrap();
+        crate::db::init(&conn).unwrap();
+
+        for (symbol, day) in [("ffUSD", 1), ("ffUSD", 2), ("ffBTC", 2), ("ffUSD", 3)] {
+            conn.execute(
+                "INSERT INTO trades (symbol, mts, amount, rate, period) VALUES (?1, ?2, 100, 0.0002, 2)",
+                params![symbol, Utc.with_ymd_and_hms(2023, 1, day, 0, 0, 0).unwrap()],
+            )
+            .unwrap();
+        }
+
+        let filter = Filter {
+            symbols: vec!["fUSD".into()],
+            start: Some(Utc.with_ymd_and_hms(2023, 1, 2, 0, 0, 0).unwrap()),
+            end: Some(Utc.with_ymd_and_hms(2023, 1, 3, 0, 0, 0).unwrap()),
+            ..Default::default()
+        };
+        let mut out = vec![];
+        let count = export(&conn, Table::Trades, &filter, Format::Csv, &mut out).unwrap();
+
+        assert_eq!(count, 1);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "symbol,mts,amount,rate,period,id\nffUSD,2023-01-02T00:00:00+00:00,100,0.0002,2,\n"
+        );
+    }
+}
@@ -0,0 +1,542 @@
+use anyhow::{anyhow, Result};
+use axum::extract::{Path, Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use log::{error, info, warn};
+use secrecy::{ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+
+use crate::bot::Bot;
+use crate::risk::Rejected;
+use crate::strategy::lending::Api;
+
+const DEFAULT_LISTEN: &str = "127.0.0.1:8080";
+const DEFAULT_METRICS_PATH: &str = "/metrics";
+// fixed GET routes a custom metrics path must not shadow
+const ROUTES: &[&str] = &[
+    "/health",
+    "/strategies",
+    "/balances",
+    "/offers",
+    "/credits",
+    "/rates",
+    "/decisions",
+    "/kill-switch",
+];
+
+// Status and control API. Read endpoints are open to whoever can reach `listen`, control
+// endpoints require `Authorization: Bearer <token>` and are disabled without a token. Metrics
+// are served in the Prometheus format on `metrics_path`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub listen: Option<String>,
+    pub token: Option<Secret<String>>,
+    pub metrics_path: Option<String>,
+}
+
+impl Config {
+    pub fn listen(&self) -> &str {
+        self.listen.as_deref().unwrap_or(DEFAULT_LISTEN)
+    }
+
+    pub fn metrics_path(&self) -> &str {
+        self.metrics_path.as_deref().unwrap_or(DEFAULT_METRICS_PATH)
+    }
+
+    pub fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        if self.listen().parse::<SocketAddr>().is_err() {
+            errors.push(format!(
+                "{path}.listen: {:?} is not an address such as {}",
+                self.listen(),
+                DEFAULT_LISTEN
+            ));
+        }
+        if self
+            .token
+            .as_ref()
+            .is_some_and(|token| token.expose_secret().len() < 16)
+        {
+            errors.push(format!("{path}.token: must be at least 16 characters"));
+        }
+        let metrics_path = self.metrics_path();
+        if !metrics_path.starts_with('/') || metrics_path.contains(':') {
+            errors.push(format!(
+                "{path}.metrics_path: {metrics_path:?} is not a path such as {DEFAULT_METRICS_PATH}"
+            ));
+        } else if ROUTES.contains(&metrics_path) {
+            errors.push(format!(
+                "{path}.metrics_path: {metrics_path:?} is already used by the api"
+            ));
+        }
+    }
+}
+
+struct AppState {
+    bot: Arc<Bot>,
+    token: RwLock<Option<Secret<String>>>,
+}
+
+// Applies configuration changes that do not need a new listening socket.
+pub struct Handle(Arc<AppState>);
+
+impl Handle {
+    pub fn configure(&self, config: &Config) {
+        *self.0.token.write().unwrap_or_else(|e| e.into_inner()) = config.token.clone();
+    }
+}
+
+type AppResult = std::result::Result<Json<Value>, ApiError>;
+
+struct ApiError(StatusCode, String);
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> Self {
+        if err.is::<Rejected>() {
+            return Self(StatusCode::CONFLICT, err.to_string());
+        }
+        Self(StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", err))
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(json!({ "error": self.1 }))).into_response()
+    }
+}
+
+// Binds the listening socket and serves requests in the background.
+pub async fn start(config: &Config, bot: Arc<Bot>) -> Result<Handle> {
+    let listener = tokio::net::TcpListener::bind(config.listen())
+        .await
+        .map_err(|err| anyhow!("failed to listen on {}: {:?}", config.listen(), err))?;
+    info!("http api listening on {}", config.listen());
+
+    let state = Arc::new(AppState {
+        bot,
+        token: RwLock::new(config.token.clone()),
+    });
+    let app = router(config, state.clone());
+
+    tokio::spawn(async move {
+        if let Err(e) = axum::serve(listener, app).await {
+            error!("http api stopped: {:?}", e);
+        }
+    });
+
+    Ok(Handle(state))
+}
+
+fn router(config: &Config, state: Arc<AppState>) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/strategies", get(strategies))
+        .route("/balances", get(balances))
+        .route("/offers", get(offers))
+        .route("/credits", get(credits))
+        .route("/rates", get(rates))
+        .route("/decisions", get(decisions))
+        .route("/kill-switch", get(kill_switch))
+        .route("/kill-switch/engage", post(engage))
+        .route("/kill-switch/release", post(release))
+        .route("/strategies/:exchange/:account/:symbol/pause", post(pause))
+        .route(
+            "/strategies/:exchange/:account/:symbol/resume",
+            post(resume),
+        )
+        .route("/offers/:exchange/:account/:id/cancel", post(cancel))
+        .route(config.metrics_path(), get(metrics))
+        .with_state(state)
+}
+
+fn authorize(state: &AppState, headers: &HeaderMap) -> std::result::Result<(), ApiError> {
+    let token = state.token.read().unwrap_or_else(|e| e.into_inner());
+    let token = token.as_ref().ok_or_else(|| {
+        ApiError(
+            StatusCode::FORBIDDEN,
+            "control endpoints are disabled, set http.token".into(),
+        )
+    })?;
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+
+    if constant_time_eq(given.as_bytes(), token.expose_secret().as_bytes()) {
+        Ok(())
+    } else {
+        Err(ApiError(StatusCode::UNAUTHORIZED, "invalid token".into()))
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+async fn health(State(state): State<Arc<AppState>>) -> AppResult {
+    let strategies = state.bot.strategies().await;
+    let healthy = strategies.iter().all(|s| s.status.last_error.is_none());
+    let halted = state.bot.risk().halted();
+    Ok(Json(json!({
+        "status": match (&halted, healthy) {
+            (Some(_), _) => "halted",
+            (None, true) => "ok",
+            (None, false) => "degraded",
+        },
+        "halted": halted,
+        "strategies": strategies
+            .iter()
+            .map(|s| json!({
+                "id": s.id,
+                "paused": s.paused,
+                "last_sync": s.status.last_sync,
+                "last_exec": s.status.last_exec,
+                "last_error": s.status.last_error,
+            }))
+            .collect::<Vec<_>>(),
+    })))
+}
+
+async fn metrics() -> Result<Response, ApiError> {
+    let (content_type, body) = crate::metrics::render()?;
+    Ok(([(header::CONTENT_TYPE, content_type)], body).into_response())
+}
+
+async fn strategies(State(state): State<Arc<AppState>>) -> AppResult {
+    Ok(Json(json!(state.bot.strategies().await)))
+}
+
+async fn rates(State(state): State<Arc<AppState>>) -> AppResult {
+    Ok(Json(json!(state
+        .bot
+        .strategies()
+        .await
+        .iter()
+        .map(|s| json!({
+            "exchange": s.exchange,
+            "account": s.account,
+            "symbol": s.symbol,
+            "rate": s.status.rate,
+            "updated": s.status.rate_updated,
+        }))
+        .collect::<Vec<_>>())))
+}
+
+#[derive(Deserialize)]
+struct DecisionsQuery {
+    limit: Option<usize>,
+}
+
+async fn decisions(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<DecisionsQuery>,
+) -> AppResult {
+    let mut decisions: Vec<Value> = vec![];
+    for s in state.bot.strategies().await {
+        for d in s.status.decisions {
+            decisions.push(json!({
+                "strategy": s.id,
+                "mts": d.mts,
+                "action": d.action,
+                "detail": d.detail,
+            }));
+        }
+    }
+    decisions.sort_by(|a, b| b["mts"].as_str().cmp(&a["mts"].as_str()));
+    decisions.truncate(query.limit.unwrap_or(50));
+
+    Ok(Json(json!(decisions)))
+}
+
+// Queries every configured exchange account for each of its symbols, adding where a row came
+// from to each of them.
+async fn per_symbol<T, F>(bot: &Bot, f: F) -> AppResult
+where
+    T: Serialize,
+    F: Fn(&dyn Api, &str) -> Result<Vec<T>> + Send + 'static,
+{
+    let (exchanges, risk) = (bot.exchanges(), bot.risk().clone());
+    let rows = tokio::task::spawn_blocking(move || -> Result<Vec<Value>> {
+        let mut rows = vec![];
+        for exch in exchanges
+            .iter()
+            .filter(|e| !e.params().strategies.is_empty())
+        {
+            let client = exch.lending_client(&risk)?;
+            for config in &exch.params().strategies {
+                for item in f(client.as_ref(), config.symbol())? {
+                    let mut row = json!({
+                        "exchange": exch.name(),
+                        "account": exch.account(),
+                        "symbol": config.symbol(),
+                    });
+                    match serde_json::to_value(item)? {
+                        Value::Object(fields) => row.as_object_mut().unwrap().extend(fields),
+                        value => row["value"] = value,
+                    }
+                    rows.push(row);
+                }
+            }
+        }
+        Ok(rows)
+    })
+    .await
+    .map_err(|e| anyhow!(e))??;
+
+    Ok(Json(json!(rows)))
+}
+
+async fn balances(State(state): State<Arc<AppState>>) -> AppResult {
+    per_symbol(&state.bot, |client, symbol| {
+        Ok(vec![json!({ "available": client.balance(symbol)? })])
+    })
+    .await
+}
+
+async fn offers(State(state): State<Arc<AppState>>) -> AppResult {
+    per_symbol(&state.bot, |client, symbol| client.active_offers(symbol)).await
+}
+
+async fn credits(State(state): State<Arc<AppState>>) -> AppResult {
+    per_symbol(&state.bot, |client, symbol| client.credits(symbol)).await
+}
+
+async fn kill_switch(State(state): State<Arc<AppState>>) -> AppResult {
+    let halted = state.bot.risk().halted();
+    Ok(Json(
+        json!({ "engaged": halted.is_some(), "reason": halted }),
+    ))
+}
+
+#[derive(Deserialize)]
+struct EngageQuery {
+    reason: Option<String>,
+}
+
+async fn engage(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Query(query): Query<EngageQuery>,
+) -> AppResult {
+    authorize(&state, &headers)?;
+    let reason = query
+        .reason
+        .unwrap_or_else(|| "engaged through the http api".into());
+    state.bot.risk().engage(&reason)?;
+    warn!("kill switch engaged through the http api: {}", reason);
+
+    kill_switch(State(state)).await
+}
+
+async fn release(State(state): State<Arc<AppState>>, headers: HeaderMap) -> AppResult {
+    authorize(&state, &headers)?;
+    if state.bot.risk().release()? {
+        warn!("kill switch released through the http api");
+    }
+
+    kill_switch(State(state)).await
+}
+
+async fn set_paused(
+    state: &AppState,
+    headers: &HeaderMap,
+    (exchange, account, symbol): (String, String, String),
+    paused: bool,
+) -> AppResult {
+    authorize(state, headers)?;
+    if state
+        .bot
+        .set_paused(&exchange, &account, &symbol, paused)
+        .await
+    {
+        Ok(Json(json!({ "paused": paused })))
+    } else {
+        Err(ApiError(
+            StatusCode::NOT_FOUND,
+            format!("no strategy {exchange} {account} {symbol}"),
+        ))
+    }
+}
+
+async fn pause(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(strategy): Path<(String, String, String)>,
+) -> AppResult {
+    set_paused(&state, &headers, strategy, true).await
+}
+
+async fn resume(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path(strategy): Path<(String, String, String)>,
+) -> AppResult {
+    set_paused(&state, &headers, strategy, false).await
+}
+
+async fn cancel(
+    State(state): State<Arc<AppState>>,
+    headers: HeaderMap,
+    Path((exchange, account, id)): Path<(String, String, u32)>,
+) -> AppResult {
+    authorize(&state, &headers)?;
+    let exch = state
+        .bot
+        .exchanges()
+        .into_iter()
+        .find(|e| e.name().eq_ignore_ascii_case(&exchange) && e.account() == account)
+        .ok_or_else(|| {
+            ApiError(
+                StatusCode::NOT_FOUND,
+                format!("no exchange {exchange} {account}"),
+            )
+        })?;
+
+    let risk = state.bot.risk().clone();
+    tokio::task::spawn_blocking(move || exch.lending_client(&risk)?.cancel_offer(id))
+        .await
+        .map_err(|e| anyhow!(e))??;
+    info!("offer {} cancelled through the http api", id);
+
+    Ok(Json(json!({ "cancelled": id })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{constant_time_eq, router, AppState, Config, Handle};
+    use crate::bot::Bot;
+    use crate::db;
+    use crate::exchange::{Exchange, Params};
+    use crate::notify::Notifier;
+    use crate::risk::Risk;
+    use crate::strategy::{self, lending};
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Method, Request, StatusCode};
+    use axum::Router;
+    use secrecy::Secret;
+    use serde_json::Value;
+    use std::sync::{Arc, RwLock};
+    use tower::ServiceExt;
+
+    const TOKEN: &str = "0123456789abcdef";
+
+    #[test]
+    fn compare_tokens() {
+        assert!(constant_time_eq(b"secret token", b"secret token"));
+        assert!(!constant_time_eq(b"secret token", b"secret tokem"));
+        assert!(!constant_time_eq(b"secret", b"secret token"));
+        assert!(!constant_time_eq(b"", b"secret token"));
+    }
+
+    async fn call(app: &Router, method: Method, uri: &str, token: &str) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn control_endpoints() {
+        let path = std::env::temp_dir().join(format!("tradebot-http-{}.db", std::process::id()));
+        let db_pool = db::get_pool(Some(db::Config {
+            path: path.to_string_lossy().into(),
+            ..Default::default()
+        }))
+        .unwrap();
+        let bot = Bot::new(db_pool, Notifier::disabled(), Risk::default())
+            .await
+            .unwrap();
+        bot.apply(&[Exchange::Bitfinex(Params {
+            account: None,
+            api_key: Secret::new("key".into()),
+            api_secret: Secret::new("secret".into()),
+            strategies: vec![strategy::Config::Lending(lending::Config {
+                symbol: "fUSD".into(),
+                lending_size: None,
+                min_apy: None,
+                max_apy: None,
+                reserved_amount_1: None,
+                reserved_amount_2: None,
+                tiers: None,
+                sync_schedule: None,
+                offer_schedule: None,
+                market: None,
+                sweep: None,
+            })],
+            cancel_on_exit: None,
+        })])
+        .await
+        .unwrap();
+
+        let config = Config {
+            listen: None,
+            token: Some(Secret::new(TOKEN.into())),
+            metrics_path: None,
+        };
+        let state = Arc::new(AppState {
+            bot: Arc::new(bot),
+            token: RwLock::new(config.token.clone()),
+        });
+        let app = router(&config, state.clone());
+        let pause = "/strategies/bitfinex/main/fUSD/pause";
+
+        let (status, health) = call(&app, Method::GET, "/health", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(health["status"], "ok");
+        assert_eq!(health["strategies"][0]["paused"], false);
+
+        let (status, _) = call(&app, Method::POST, pause, "").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = call(&app, Method::POST, pause, "0123456789abcdeX").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = call(
+            &app,
+            Method::POST,
+            "/strategies/bitfinex/main/fBTC/pause",
+            TOKEN,
+        )
+        .await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, body) = call(&app, Method::POST, pause, TOKEN).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["paused"], true);
+        let (_, health) = call(&app, Method::GET, "/health", "").await;
+        assert_eq!(health["strategies"][0]["paused"], true);
+
+        // a rotated token replaces the old one, no token disables control
+        let handle = Handle(state);
+        handle.configure(&Config {
+            token: Some(Secret::new("fedcba9876543210".into())),
+            ..config
+        });
+        let resume = "/strategies/bitfinex/main/fUSD/resume";
+        let (status, _) = call(&app, Method::POST, resume, TOKEN).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, body) = call(&app, Method::POST, resume, "fedcba9876543210").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["paused"], false);
+
+        handle.configure(&Config {
+            listen: None,
+            token: None,
+            metrics_path: None,
+        });
+        let (status, _) = call(&app, Method::POST, pause, "fedcba9876543210").await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -0,0 +1,223 @@
+use anyhow::{anyhow, Result};
+use chrono::{Duration, TimeZone, Utc};
+use log::warn;
+use rusqlite::{params, Connection};
+use serde::{
+    de::{Error, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_json::Value;
+use std::fmt;
+use std::io::Read;
+use std::str::FromStr;
+
+use crate::exchange::bitfinex::api::Trade;
+
+const BATCH_SIZE: usize = 10000;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Format {
+    Csv,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "csv" => Ok(Self::Csv),
+            "json" => Ok(Self::Json),
+            _ => Err(anyhow!("unknown format: {s}")),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub read: u64,
+    pub inserted: u64,
+    pub duplicates: u64,
+    pub rejected: u64,
+}
+
+fn validate(trade: &Trade) -> Result<()> {
+    let earliest = Utc.with_ymd_and_hms(2013, 1, 1, 0, 0, 0).unwrap();
+    let latest = Utc::now() + Duration::days(1);
+
+    if !(earliest..latest).contains(&trade.mts) {
+        Err(anyhow!("timestamp out of range: {}", trade.mts))
+    } else if !trade.amount.is_finite() || trade.amount == 0. {
+        Err(anyhow!("invalid amount: {}", trade.amount))
+    } else if !trade.rate.is_finite() || trade.rate <= 0. || trade.rate >= 0.1 {
+        Err(anyhow!("invalid rate: {}", trade.rate))
+    } else if !(2..=120).contains(&trade.period) {
+        Err(anyhow!("invalid period: {}", trade.period))
+    } else {
+        Ok(())
+    }
+}
+
+// Validates rows and writes them to `trades` in batched transactions. Rows already stored for
+// the symbol (same trade id) are skipped.
+struct Importer<'a> {
+    conn: &'a mut Connection,
+    symbol: String,
+    batch: Vec<Trade>,
+    summary: Summary,
+}
+
+impl<'a> Importer<'a> {
+    fn push(&mut self, row: Result<Trade>) -> Result<()> {
+        self.summary.read += 1;
+        match row.and_then(|t| validate(&t).map(|_| t)) {
+            Ok(trade) => {
+                self.batch.push(trade);
+                if self.batch.len() >= BATCH_SIZE {
+                    self.flush()?;
+                }
+            }
+            Err(e) => {
+                warn!("row {} rejected: {}", self.summary.read, e);
+                self.summary.rejected += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR IGNORE INTO trades (symbol, mts, amount, rate, period, id)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            )?;
+            for t in self.batch.drain(..) {
+                match stmt.execute(params![
+                    &self.symbol,
+                    t.mts,
+                    t.amount,
+                    t.rate,
+                    t.period,
+                    t.id
+                ]) {
+                    Ok(0) => self.summary.duplicates += 1,
+                    Ok(_) => self.summary.inserted += 1,
+                    Err(err) => return Err(anyhow!("failed to import trade: {:?}", err)),
+                }
+            }
+        }
+        tx.commit()?;
+
+        Ok(())
+    }
+}
+
+struct TradeSeq<'a, 'b>(&'a mut Importer<'b>);
+
+impl<'de, 'a, 'b> Visitor<'de> for TradeSeq<'a, 'b> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("array of [ID, MTS, AMOUNT, RATE, PERIOD] arrays")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        while let Some(value) = seq.next_element::<Value>()? {
+            let row = Trade::deserialize(value).map_err(|e| anyhow!(e));
+            self.0.push(row).map_err(A::Error::custom)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn import<R: Read>(
+    conn: &mut Connection,
+    symbol: &str,
+    format: Format,
+    input: R,
+) -> Result<Summary> {
+    let mut importer = Importer {
+        conn,
+        symbol: format!("f{symbol}"),
+        batch: Vec::with_capacity(BATCH_SIZE),
+        summary: Summary::default(),
+    };
+
+    match format {
+        Format::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .trim(csv::Trim::All)
+                .from_reader(input);
+            for (idx, record) in reader.records().enumerate() {
+                // a malformed line is rejected like an invalid row, only reading may fail
+                let record = match record {
+                    Ok(record) => record,
+                    Err(e) if e.is_io_error() => return Err(e.into()),
+                    Err(e) => {
+                        importer.push(Err(anyhow!(e)))?;
+                        continue;
+                    }
+                };
+                // skip an optional header line
+                if idx == 0 && record.get(0).is_some_and(|f| f.parse::<u64>().is_err()) {
+                    continue;
+                }
+                importer.push(record.deserialize(None).map_err(|e| anyhow!(e)))?;
+            }
+        }
+        Format::Json => {
+            serde_json::Deserializer::from_reader(input)
+                .deserialize_seq(TradeSeq(&mut importer))?;
+        }
+    }
+    importer.flush()?;
+
+    Ok(importer.summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{import, Format};
+    use rusqlite::{params, Connection};
+
+    #[test]
+    fn import_csv_dedup_and_validate() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        crate::db::init(&conn).unwrap();
+
+        let csv = "ID,MTS,AMOUNT,RATE,PERIOD
+1,1672531200000,100,0.0002,2
+2,1672531260000,-50,0.0003,30
+2,1672531260000,-50,0.0003,30
+3,1672531320000,10,-0.0001,2
+4,1672531380000,10,0.0002,7
+5,1672531390000,10
+";
+        let summary = import(&mut conn, "fUSD", Format::Csv, csv.as_bytes()).unwrap();
+        assert_eq!(summary.read, 6);
+        assert_eq!(summary.inserted, 3);
+        assert_eq!(summary.duplicates, 1);
+        assert_eq!(summary.rejected, 2);
+
+        let json = "[[4,1672531380000,10,0.0002,7],[5,1672531440000,20,0.0004,\"x\"],[6,1672531500000,20,0.0004,120]]";
+        let summary = import(&mut conn, "fUSD", Format::Json, json.as_bytes()).unwrap();
+        assert_eq!(summary.read, 3);
+        assert_eq!(summary.inserted, 1);
+        assert_eq!(summary.duplicates, 1);
+        assert_eq!(summary.rejected, 1);
+
+        let count: u32 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM trades WHERE symbol = 'ffUSD'",
+                params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 4);
+    }
+}
@@ -1,4 +1,16 @@
+pub mod allocate;
+pub mod bot;
+pub mod cli;
 pub mod config;
 pub mod db;
+pub mod digest;
 pub mod exchange;
+pub mod export;
+pub mod http;
+pub mod import;
+pub mod metrics;
+pub mod notify;
+pub mod risk;
+pub mod secrets;
 pub mod strategy;
+pub mod telemetry;
@@ -1,58 +1,190 @@
-use std::sync::OnceLock;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use anyhow::anyhow;
 use clap::Parser;
-use tokio_cron_scheduler::{Job, JobScheduler};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_cron_scheduler::Job;
 
+use tradebot::allocate;
+use tradebot::bot::{self, Bot};
+use tradebot::cli::{self, Command, Opts};
 use tradebot::config;
 use tradebot::db;
-use tradebot::db::DbPool;
-use tradebot::exchange;
-
-#[derive(Parser)]
-#[clap(version = "0.1")]
-struct Opts {
-    #[clap(short, long, default_value = "config.toml")]
-    config: String,
-}
-
-static EXCHANGE: OnceLock<Vec<exchange::Exchange>> = OnceLock::new();
-static DB_POOL: OnceLock<DbPool> = OnceLock::new();
+use tradebot::digest;
+use tradebot::notify::Notifier;
+use tradebot::risk::Risk;
+use tradebot::telemetry;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::init();
-
     let cli_opts: Opts = Opts::parse();
 
-    let conf = config::Config::from_file(cli_opts.config.as_str())?;
-    let exchange = conf.exchanges.clone();
+    if let Some(Command::Secrets { command }) = cli_opts.command {
+        let _telemetry = telemetry::init(None)?;
+        return cli::secrets(command, &cli_opts.config);
+    }
+
+    let conf = config::Config::from_file(cli_opts.config.as_str());
+    let _telemetry = telemetry::init(conf.as_ref().ok().and_then(|conf| conf.logging.as_ref()))?;
+    let conf = conf?;
+    match cli_opts.command {
+        Some(Command::CheckConfig) => {
+            println!("configuration ok");
+            return Ok(());
+        }
+        Some(Command::KillSwitch { command }) => return cli::kill_switch(command, &conf),
+        _ => {}
+    }
     let db_pool = db::get_pool(conf.database.clone())?;
 
-    EXCHANGE.set(exchange).map_err(|e| anyhow!("{:?}", e))?;
-    DB_POOL.set(db_pool).map_err(|e| anyhow!("{:?}", e))?;
-
-    let sched = JobScheduler::new().await?;
-
-    if let Some(exchange_cfg) = EXCHANGE.get() {
-        for exch in exchange_cfg {
-            if let Some(db_pool) = DB_POOL.get() {
-                let bot = Job::new_repeated_async(Duration::from_secs(60), |_, _| {
-                    Box::pin(async {
-                        if let Err(e) = exch.exec(db_pool.clone()).await {
-                            log::error!("{:?}", e);
-                        }
-                    })
-                })?;
-                sched.add(bot).await?;
-            }
+    match cli_opts.command {
+        None | Some(Command::Run) => {}
+        Some(command) => {
+            return tokio::task::spawn_blocking(move || cli::exec(command, &conf, db_pool)).await?
         }
     }
 
-    sched.start().await?;
+    let notifier = Notifier::start(conf.notifications.clone().unwrap_or_default());
+    let risk = Risk::new(conf.risk.clone().unwrap_or_default());
+    let bot = Arc::new(Bot::new(db_pool.clone(), notifier, risk).await?);
+
+    bot.apply(&conf.exchanges).await?;
+
+    if let Some(config) = conf.digest.clone() {
+        let (weak, db_pool) = (Arc::downgrade(&bot), db_pool.clone());
+        let send = bot::job(
+            &config.schedule(),
+            Box::new(move |_, _| {
+                let (weak, config, db_pool) = (weak.clone(), config.clone(), db_pool.clone());
+                Box::pin(async move {
+                    let Some(bot) = weak.upgrade() else { return };
+                    if let Err(e) = digest::run(&bot, &config, db_pool).await {
+                        log::error!("failed to deliver digest: {:?}", e);
+                    }
+                })
+            }),
+        )?;
+        bot.add_job(send).await?;
+    }
+
+    if let Some(config) = conf.allocation.clone() {
+        let (weak, db_pool) = (Arc::downgrade(&bot), db_pool.clone());
+        let recommend = bot::job(
+            &config.schedule(),
+            Box::new(move |_, _| {
+                let (weak, config, db_pool) = (weak.clone(), config.clone(), db_pool.clone());
+                Box::pin(async move {
+                    let Some(bot) = weak.upgrade() else { return };
+                    if let Err(e) = allocate::run(&bot, &config, db_pool).await {
+                        log::error!("failed to plan allocation: {:?}", e);
+                    }
+                })
+            }),
+        )?;
+        bot.add_job(recommend).await?;
+    }
+
+    if let Some(retention) = conf.retention.clone() {
+        let interval = Duration::from_secs(retention.interval_hours() * 3600);
+        let prune = Job::new_repeated_async(interval, move |_, _| {
+            let retention = retention.clone();
+            let db_pool = db_pool.clone();
+            Box::pin(async move {
+                let result = tokio::task::spawn_blocking(move || {
+                    let conn = db_pool.get().map_err(|e| anyhow!(e))?;
+                    db::retention::prune(&conn, &retention)
+                })
+                .await
+                .map_err(|e| anyhow!(e))
+                .and_then(|result| result);
+                if let Err(e) = result {
+                    log::error!("{:?}", e);
+                }
+            })
+        })?;
+        bot.add_job(prune).await?;
+    }
+
+    bot.start().await?;
 
+    let http = match &conf.http {
+        Some(http) => Some(tradebot::http::start(http, bot.clone()).await?),
+        None => None,
+    };
+
+    // reload on SIGHUP or when the configuration file changes
+    let mut sigterm = signal(SignalKind::terminate())?;
+    let mut sighup = signal(SignalKind::hangup())?;
+    let mut poll = tokio::time::interval(Duration::from_secs(5));
+    let mut modified = modified_time(&cli_opts.config);
+    let mut conf = conf;
     loop {
-        tokio::time::sleep(core::time::Duration::from_secs(600)).await;
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = sigterm.recv() => break,
+            _ = sighup.recv() => {}
+            _ = poll.tick() => {
+                let m = modified_time(&cli_opts.config);
+                if m == modified {
+                    continue;
+                }
+                modified = m;
+            }
+        }
+
+        match reload(&bot, http.as_ref(), &cli_opts.config, &conf).await {
+            Ok(new_conf) => {
+                log::info!("configuration reloaded from {}", cli_opts.config);
+                conf = new_conf;
+            }
+            Err(e) => log::error!("rejected configuration {}: {:?}", cli_opts.config, e),
+        }
     }
+    log::info!("shutting down");
+
+    tokio::select! {
+        result = bot.shutdown() => result,
+        _ = tokio::signal::ctrl_c() => {
+            log::warn!("interrupted again, exiting without waiting");
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn reload(
+    bot: &Bot,
+    http: Option<&tradebot::http::Handle>,
+    path: &str,
+    current: &config::Config,
+) -> anyhow::Result<Arc<config::Config>> {
+    let conf = config::Config::from_file(path)?;
+    bot.apply(&conf.exchanges).await?;
+    bot.notifier()
+        .configure(conf.notifications.clone().unwrap_or_default());
+    bot.risk().configure(conf.risk.clone().unwrap_or_default());
+    if let (Some(handle), Some(config)) = (http, &conf.http) {
+        handle.configure(config);
+    }
+    let listen = |conf: &config::Config| {
+        conf.http
+            .as_ref()
+            .map(|http| (http.listen().to_string(), http.metrics_path().to_string()))
+    };
+    if conf.database != current.database
+        || conf.retention != current.retention
+        || conf.digest != current.digest
+        || conf.allocation != current.allocation
+        || conf.logging != current.logging
+        || listen(&conf) != listen(current)
+    {
+        log::warn!(
+            "database, retention, digest, allocation, logging, http.listen and http.metrics_path changes take effect after a restart"
+        );
+    }
+    Ok(conf)
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
 }
@@ -0,0 +1,163 @@
+use anyhow::{anyhow, Result};
+use prometheus::{
+    register_gauge_vec, register_histogram_vec, register_int_counter_vec, Encoder, GaugeVec,
+    HistogramVec, IntCounterVec, TextEncoder,
+};
+use std::sync::LazyLock;
+
+// Metrics live in the default registry and are served by the http api on `http.metrics_path`.
+// Per symbol metrics are labelled with exchange, account and symbol.
+
+const SYMBOL_LABELS: &[&str] = &["exchange", "account", "symbol"];
+const STEP_LABELS: &[&str] = &["exchange", "account", "symbol", "step"];
+const API_LABELS: &[&str] = &["exchange", "account", "endpoint"];
+
+pub static BALANCE_AVAILABLE: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "tradebot_balance_available",
+        "Funding balance available for new offers",
+        SYMBOL_LABELS
+    )
+    .unwrap()
+});
+
+pub static BALANCE_LENT: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "tradebot_balance_lent",
+        "Funding balance currently lent out",
+        SYMBOL_LABELS
+    )
+    .unwrap()
+});
+
+pub static ESTIMATED_RATE: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "tradebot_estimated_rate",
+        "Daily lending rate estimated from recent trades",
+        SYMBOL_LABELS
+    )
+    .unwrap()
+});
+
+pub static YIELD_LEND: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "tradebot_yield_lend",
+        "Average daily lending yield reported by the exchange",
+        SYMBOL_LABELS
+    )
+    .unwrap()
+});
+
+pub static CIRCUIT_BREAKER: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "tradebot_circuit_breaker_open",
+        "1 while offers are paused because market data looks abnormal",
+        SYMBOL_LABELS
+    )
+    .unwrap()
+});
+
+pub static OFFERS_SUBMITTED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "tradebot_offers_submitted_total",
+        "Funding offers submitted",
+        SYMBOL_LABELS
+    )
+    .unwrap()
+});
+
+pub static OFFERS_CANCELLED: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "tradebot_offers_cancelled_total",
+        "Funding offers cancelled",
+        SYMBOL_LABELS
+    )
+    .unwrap()
+});
+
+pub static API_REQUEST_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "tradebot_api_request_duration_seconds",
+        "Exchange api request latency",
+        API_LABELS
+    )
+    .unwrap()
+});
+
+pub static API_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "tradebot_api_errors_total",
+        "Failed exchange api requests",
+        API_LABELS
+    )
+    .unwrap()
+});
+
+pub static TICK_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "tradebot_tick_duration_seconds",
+        "Duration of strategy sync and exec steps",
+        STEP_LABELS
+    )
+    .unwrap()
+});
+
+pub static LAST_SUCCESS: LazyLock<GaugeVec> = LazyLock::new(|| {
+    register_gauge_vec!(
+        "tradebot_last_success_timestamp_seconds",
+        "Unix time of the last successful strategy step",
+        STEP_LABELS
+    )
+    .unwrap()
+});
+
+// Api path with symbols such as fUSD or tBTCUSD replaced, to keep the endpoint label bounded
+pub fn endpoint(path: &str) -> String {
+    path.split('/')
+        .map(|segment| {
+            let mut chars = segment.chars();
+            let is_symbol = matches!(chars.next(), Some('f' | 't'))
+                && segment.len() > 1
+                && chars.all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == ':');
+            if is_symbol {
+                "{symbol}"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// All registered metrics in the Prometheus text format, with its content type
+pub fn render() -> Result<(String, String)> {
+    let encoder = TextEncoder::new();
+    let mut buffer = vec![];
+    encoder
+        .encode(&prometheus::gather(), &mut buffer)
+        .map_err(|err| anyhow!("failed to encode metrics: {:?}", err))?;
+
+    Ok((
+        encoder.format_type().to_string(),
+        String::from_utf8(buffer)?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::endpoint;
+
+    #[test]
+    fn endpoint_label() {
+        assert_eq!(endpoint("v2/trades/fUSD/hist"), "v2/trades/{symbol}/hist");
+        assert_eq!(endpoint("v2/book/tBTCUSD/P3"), "v2/book/{symbol}/P3");
+        assert_eq!(
+            endpoint("v2/auth/r/funding/credits/fUSD/hist"),
+            "v2/auth/r/funding/credits/{symbol}/hist"
+        );
+        assert_eq!(
+            endpoint("v2/auth/w/funding/offer/submit"),
+            "v2/auth/w/funding/offer/submit"
+        );
+    }
+}
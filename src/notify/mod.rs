@@ -0,0 +1,293 @@
+mod smtp;
+mod webhook;
+
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+
+use crate::secrets;
+
+// Events a channel can subscribe to
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Kind {
+    CreditOpened,
+    CreditClosed,
+    LargePayout,
+    ApiErrors,
+    IdleBalance,
+    CircuitBreaker,
+    Digest,
+    Allocation,
+}
+
+// `[notifications]`, thresholds of the anomalies and where to send them
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // the same alert from the same strategy is sent at most once per this many minutes
+    pub throttle_minutes: Option<u64>,
+    // payouts of at least this amount are reported as large_payout
+    pub large_payout: Option<f64>,
+    // steps failed on exchange requests in a row before api_errors is reported
+    pub api_errors: Option<u32>,
+    // hours the available balance may stay unlent before idle_balance is reported
+    pub idle_hours: Option<u32>,
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+}
+
+impl Config {
+    pub fn throttle(&self) -> Duration {
+        Duration::from_secs(self.throttle_minutes.unwrap_or(60) * 60)
+    }
+
+    pub fn large_payout(&self) -> f64 {
+        self.large_payout.unwrap_or(10.)
+    }
+
+    pub fn api_errors(&self) -> u32 {
+        self.api_errors.unwrap_or(3)
+    }
+
+    pub fn idle_hours(&self) -> u32 {
+        self.idle_hours.unwrap_or(6)
+    }
+
+    pub fn subscribed(&self, kind: Kind) -> bool {
+        self.channels.iter().any(|c| c.events().contains(&kind))
+    }
+
+    pub fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        if self
+            .large_payout
+            .is_some_and(|amount| !(amount.is_finite() && amount >= 0.))
+        {
+            errors.push(format!("{path}.large_payout: must not be negative"));
+        }
+        if self.api_errors == Some(0) {
+            errors.push(format!("{path}.api_errors: must be at least 1"));
+        }
+        if self.idle_hours == Some(0) {
+            errors.push(format!("{path}.idle_hours: must be at least 1"));
+        }
+        for (idx, channel) in self.channels.iter().enumerate() {
+            channel.validate(&format!("{path}.channels[{idx}]"), errors);
+        }
+    }
+
+    pub fn resolve_secrets(
+        &mut self,
+        path: &str,
+        resolver: &mut secrets::Resolver,
+        errors: &mut Vec<String>,
+    ) {
+        for (idx, channel) in self.channels.iter_mut().enumerate() {
+            let path = format!("{path}.channels[{idx}]");
+            let (key, value) = match channel {
+                Channel::Webhook(config) => ("url", Some(&mut config.url)),
+                Channel::Smtp(config) => ("password", config.password.as_mut()),
+            };
+            if let Some(value) = value {
+                match resolver.resolve(value) {
+                    Ok(resolved) => *value = resolved,
+                    Err(err) => errors.push(format!("{path}.{key}: {err}")),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Channel {
+    Webhook(webhook::Config),
+    Smtp(smtp::Config),
+}
+
+impl<'de> Deserialize<'de> for Channel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (name, fields) = crate::config::tagged(deserializer)?;
+        match name.as_str() {
+            "Webhook" => Ok(Self::Webhook(crate::config::variant(fields)?)),
+            "Smtp" => Ok(Self::Smtp(crate::config::variant(fields)?)),
+            _ => Err(de::Error::unknown_variant(&name, &["Webhook", "Smtp"])),
+        }
+    }
+}
+
+impl Channel {
+    fn events(&self) -> &[Kind] {
+        match self {
+            Self::Webhook(config) => &config.events,
+            Self::Smtp(config) => &config.events,
+        }
+    }
+
+    fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        if self.events().is_empty() {
+            errors.push(format!("{path}.events: subscribes to nothing"));
+        }
+        match self {
+            Self::Webhook(config) => config.validate(path, errors),
+            Self::Smtp(config) => config.validate(path, errors),
+        }
+    }
+
+    fn send(&self, notification: &Notification) -> anyhow::Result<()> {
+        match self {
+            Self::Webhook(config) => config.send(notification),
+            Self::Smtp(config) => config.send(notification),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Notification {
+    pub kind: Kind,
+    // the strategy it concerns, e.g. `Bitfinex main fUSD`
+    pub source: String,
+    pub title: String,
+    pub text: String,
+    pub mts: DateTime<Utc>,
+}
+
+// Hands notifications to a background thread, so a slow channel never delays a strategy. Cheap
+// to clone, all clones share the configuration.
+#[derive(Clone, Debug, Default)]
+pub struct Notifier {
+    config: Arc<RwLock<Config>>,
+    sender: Option<mpsc::Sender<Notification>>,
+}
+
+impl Notifier {
+    pub fn start(config: Config) -> Self {
+        let config = Arc::new(RwLock::new(config));
+        let (sender, receiver) = mpsc::channel();
+        let shared = config.clone();
+        std::thread::spawn(move || deliver(shared, receiver));
+
+        Self {
+            config,
+            sender: Some(sender),
+        }
+    }
+
+    // Drops every notification, for one-off commands.
+    pub fn disabled() -> Self {
+        Self::default()
+    }
+
+    pub fn configure(&self, config: Config) {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = config;
+    }
+
+    pub fn config(&self) -> RwLockReadGuard<'_, Config> {
+        self.config.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Whether any channel subscribes to `kind`, to skip work done only to notify
+    pub fn wants(&self, kind: Kind) -> bool {
+        self.sender.is_some() && self.config().subscribed(kind)
+    }
+
+    pub fn notify(&self, kind: Kind, source: &str, title: String, text: String) {
+        if !self.wants(kind) {
+            return;
+        }
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(Notification {
+                kind,
+                source: source.into(),
+                title,
+                text,
+                mts: Utc::now(),
+            });
+        }
+    }
+}
+
+fn deliver(config: Arc<RwLock<Config>>, receiver: mpsc::Receiver<Notification>) {
+    let mut throttle = Throttle::default();
+
+    for notification in receiver {
+        let config = config.read().unwrap_or_else(|e| e.into_inner()).clone();
+        if !throttle.admit(&notification, config.throttle(), Instant::now()) {
+            debug!(
+                "[{}] throttled notification: {}",
+                notification.source, notification.title
+            );
+            continue;
+        }
+
+        for channel in config
+            .channels
+            .iter()
+            .filter(|c| c.events().contains(&notification.kind))
+        {
+            if let Err(e) = channel.send(&notification) {
+                warn!(
+                    "[{}] failed to send notification: {:?}",
+                    notification.source, e
+                );
+            }
+        }
+    }
+}
+
+// Drops a notification identical in kind, source and title to one sent within the window.
+#[derive(Default)]
+struct Throttle {
+    sent: HashMap<(Kind, String, String), Instant>,
+}
+
+impl Throttle {
+    fn admit(&mut self, notification: &Notification, window: Duration, now: Instant) -> bool {
+        self.sent
+            .retain(|_, sent| now.duration_since(*sent) < window);
+
+        let key = (
+            notification.kind,
+            notification.source.clone(),
+            notification.title.clone(),
+        );
+        if self.sent.contains_key(&key) {
+            return false;
+        }
+        self.sent.insert(key, now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Kind, Notification, Throttle};
+    use chrono::Utc;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn throttle_repeated_alerts() {
+        let notification = |title: &str| Notification {
+            kind: Kind::ApiErrors,
+            source: "Bitfinex main fUSD".into(),
+            title: title.into(),
+            text: String::new(),
+            mts: Utc::now(),
+        };
+        let window = Duration::from_secs(3600);
+        let start = Instant::now();
+        let mut throttle = Throttle::default();
+
+        assert!(throttle.admit(&notification("sync failing"), window, start));
+        assert!(!throttle.admit(&notification("sync failing"), window, start));
+        assert!(throttle.admit(&notification("exec failing"), window, start));
+        assert!(throttle.admit(
+            &notification("sync failing"),
+            window,
+            start + Duration::from_secs(3601)
+        ));
+    }
+}
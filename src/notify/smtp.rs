@@ -0,0 +1,93 @@
+use anyhow::{anyhow, Result};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+
+use super::{Kind, Notification};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Security {
+    // implicit TLS, port 465 by default
+    Tls,
+    // upgraded with STARTTLS, port 587 by default
+    #[default]
+    Starttls,
+    // plain text, port 25 by default; only for a relay on the same host
+    None,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub host: String,
+    pub port: Option<u16>,
+    pub security: Option<Security>,
+    pub username: Option<String>,
+    pub password: Option<Secret<String>>,
+    pub from: String,
+    pub to: Vec<String>,
+    pub events: Vec<Kind>,
+}
+
+impl Config {
+    pub(super) fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        if self.host.is_empty() {
+            errors.push(format!("{path}.host: must not be empty"));
+        }
+        if self.username.is_some() != self.password.is_some() {
+            errors.push(format!(
+                "{path}.password: username and password go together"
+            ));
+        }
+        if self.from.parse::<Mailbox>().is_err() {
+            errors.push(format!("{path}.from: {:?} is not an address", self.from));
+        }
+        if self.to.is_empty() {
+            errors.push(format!("{path}.to: needs at least one address"));
+        }
+        for (idx, to) in self.to.iter().enumerate() {
+            if to.parse::<Mailbox>().is_err() {
+                errors.push(format!("{path}.to[{idx}]: {to:?} is not an address"));
+            }
+        }
+    }
+
+    fn transport(&self) -> Result<SmtpTransport> {
+        let builder = match self.security.unwrap_or_default() {
+            Security::Tls => SmtpTransport::relay(&self.host)?,
+            Security::Starttls => SmtpTransport::starttls_relay(&self.host)?,
+            Security::None => SmtpTransport::builder_dangerous(&self.host),
+        };
+        let builder = match self.port {
+            Some(port) => builder.port(port),
+            None => builder,
+        };
+        let builder = match (&self.username, &self.password) {
+            (Some(username), Some(password)) => builder.credentials(Credentials::new(
+                username.clone(),
+                password.expose_secret().clone(),
+            )),
+            _ => builder,
+        };
+        Ok(builder.build())
+    }
+
+    pub(super) fn send(&self, notification: &Notification) -> Result<()> {
+        let mut message = Message::builder().from(self.from.parse()?).subject(format!(
+            "[tradebot] {}: {}",
+            notification.source, notification.title
+        ));
+        for to in &self.to {
+            message = message.to(to.parse()?);
+        }
+        let message = message.body(format!("{}\n\n{}\n", notification.text, notification.mts))?;
+
+        self.transport()?
+            .send(&message)
+            .map_err(|err| anyhow!("failed to send mail via {}: {:?}", self.host, err))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+use anyhow::{anyhow, Result};
+use secrecy::{ExposeSecret, Secret};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{Kind, Notification};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    // the notification as is
+    #[default]
+    Json,
+    // incoming webhook of Slack and compatible chats
+    Slack,
+    // sendMessage of the Telegram Bot API, the url being https://api.telegram.org/bot<token>/sendMessage
+    Telegram,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // a secret, as it usually embeds a token
+    pub url: Secret<String>,
+    pub format: Option<Format>,
+    // required by Telegram
+    pub chat_id: Option<String>,
+    pub events: Vec<Kind>,
+}
+
+impl Config {
+    pub(super) fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        let url = self.url.expose_secret();
+        if !(url.starts_with("https://") || url.starts_with("http://")) {
+            errors.push(format!("{path}.url: must be an http or https url"));
+        }
+        if self.format == Some(Format::Telegram) && self.chat_id.is_none() {
+            errors.push(format!("{path}.chat_id: required by the telegram format"));
+        }
+    }
+
+    pub(super) fn send(&self, notification: &Notification) -> Result<()> {
+        let response = reqwest::blocking::Client::new()
+            .post(self.url.expose_secret())
+            .json(&self.payload(notification))
+            .send()
+            .map_err(|err| anyhow!("failed to call webhook: {}", err.without_url()))?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "webhook returned {}: {:?}",
+                response.status(),
+                response.text().unwrap_or_default()
+            ))
+        }
+    }
+
+    fn payload(&self, notification: &Notification) -> Value {
+        let text = format!(
+            "{}: {}\n{}",
+            notification.source, notification.title, notification.text
+        );
+        match self.format.unwrap_or_default() {
+            Format::Json => json!(notification),
+            Format::Slack => json!({ "text": text }),
+            Format::Telegram => json!({ "chat_id": self.chat_id, "text": text }),
+        }
+    }
+}
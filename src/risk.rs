@@ -0,0 +1,526 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::io::ErrorKind;
+use std::sync::{Arc, Mutex, RwLock, RwLockReadGuard};
+use std::time::{Duration, Instant};
+
+use crate::strategy::lending::{
+    self, Book, Credit, Info, Offer, OfferResponse, Payout, Trade, TransferResponse, Wallet,
+};
+
+const DEFAULT_KILL_SWITCH: &str = "tradebot.halt";
+// credits and offers of more days than this are long
+const LONG_PERIOD: u32 = 30;
+const HOUR: Duration = Duration::from_secs(3600);
+
+// A write refused by the kill switch or a limit, before reaching the exchange
+#[derive(Debug, thiserror::Error)]
+pub enum Rejected {
+    #[error("rejected: kill switch engaged: {0}")]
+    Halted(String),
+    #[error("rejected: {0}")]
+    Limit(String),
+}
+
+impl Rejected {
+    pub fn reason(&self) -> String {
+        match self {
+            Self::Halted(reason) => format!("kill switch engaged: {reason}"),
+            Self::Limit(reason) => reason.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    // lent and offered together, in the currency of the symbol
+    pub max_lent: Option<f64>,
+    pub max_offer: Option<f64>,
+    // of the available, lent and offered balance, what may be placed for more than 30 days
+    pub max_long_share: Option<f64>,
+    // daily rate, no offer is placed below it whatever the strategy estimates
+    pub min_rate: Option<f64>,
+    pub max_offers_per_hour: Option<u32>,
+}
+
+impl Limits {
+    // `self` where set, `other` otherwise
+    fn or(&self, other: &Self) -> Self {
+        Self {
+            max_lent: self.max_lent.or(other.max_lent),
+            max_offer: self.max_offer.or(other.max_offer),
+            max_long_share: self.max_long_share.or(other.max_long_share),
+            min_rate: self.min_rate.or(other.min_rate),
+            max_offers_per_hour: self.max_offers_per_hour.or(other.max_offers_per_hour),
+        }
+    }
+
+    fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        for (key, amount) in [("max_lent", self.max_lent), ("max_offer", self.max_offer)] {
+            if let Some(amount) = amount {
+                if !(amount.is_finite() && amount > 0.) {
+                    errors.push(format!("{path}.{key}: must be positive, got {amount}"));
+                }
+            }
+        }
+        if let Some(share) = self.max_long_share {
+            if !(0. ..=1.).contains(&share) {
+                errors.push(format!(
+                    "{path}.max_long_share: must be between 0 and 1, got {share}"
+                ));
+            }
+        }
+        if let Some(rate) = self.min_rate {
+            if !(rate > 0. && rate < 0.1) {
+                errors.push(format!(
+                    "{path}.min_rate: must be a daily rate between 0 and 0.1, got {rate}"
+                ));
+            }
+        }
+        if self.max_offers_per_hour == Some(0) {
+            errors.push(format!("{path}.max_offers_per_hour: must be at least 1"));
+        }
+    }
+
+    fn needs_exposure(&self) -> bool {
+        self.max_lent.is_some() || self.max_long_share.is_some()
+    }
+
+    fn check(
+        &self,
+        amount: f64,
+        rate: f64,
+        period: u32,
+        exposure: Option<&Exposure>,
+    ) -> std::result::Result<(), Rejected> {
+        if let Some(min_rate) = self.min_rate.filter(|min_rate| rate < *min_rate) {
+            return Err(Rejected::Limit(format!(
+                "rate {:.4}% below min_rate {:.4}%",
+                rate * 100.,
+                min_rate * 100.
+            )));
+        }
+        if let Some(max_offer) = self.max_offer.filter(|max_offer| amount > *max_offer) {
+            return Err(Rejected::Limit(format!(
+                "amount {amount:.2} above max_offer {max_offer:.2}"
+            )));
+        }
+        let Some(exposure) = exposure else {
+            return Ok(());
+        };
+        if let Some(max_lent) = self
+            .max_lent
+            .filter(|max_lent| exposure.placed + amount > *max_lent)
+        {
+            return Err(Rejected::Limit(format!(
+                "{:.2} lent and offered with this offer, above max_lent {max_lent:.2}",
+                exposure.placed + amount
+            )));
+        }
+        if let Some(max_share) = self.max_long_share.filter(|_| period > LONG_PERIOD) {
+            let total = exposure.available + exposure.placed;
+            let share = if total > 0. {
+                (exposure.long + amount) / total
+            } else {
+                1.
+            };
+            if share > max_share {
+                return Err(Rejected::Limit(format!(
+                    "{:.1}% of the balance placed for more than {LONG_PERIOD} days with this \
+                     offer, above max_long_share {:.1}%",
+                    share * 100.,
+                    max_share * 100.
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Balance of one symbol when an offer is checked
+struct Exposure {
+    available: f64,
+    // lent and offered
+    placed: f64,
+    // lent and offered for more than LONG_PERIOD days
+    long: f64,
+}
+
+// `[risk]`, enforced on every offer submitted, by strategies and commands alike. While the kill
+// switch file exists no offer or order is submitted or cancelled, it can be created and removed
+// with the kill-switch command, the http api or by hand.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // `tradebot.halt` in the working directory if omitted
+    pub kill_switch: Option<String>,
+    // offers submitted by all strategies together
+    pub max_offers_per_hour: Option<u32>,
+    // for every symbol of every account, unless overridden in `symbols`
+    #[serde(default)]
+    pub limits: Limits,
+    // keyed by symbol, e.g. `[risk.symbols.fUSD]`
+    #[serde(default)]
+    pub symbols: HashMap<String, Limits>,
+}
+
+impl Config {
+    pub fn kill_switch(&self) -> &str {
+        self.kill_switch.as_deref().unwrap_or(DEFAULT_KILL_SWITCH)
+    }
+
+    pub fn limits(&self, symbol: &str) -> Limits {
+        // keys may have been lowercased by the configuration loader
+        self.symbols
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(symbol))
+            .map(|(_, limits)| limits.or(&self.limits))
+            .unwrap_or_else(|| self.limits.clone())
+    }
+
+    pub fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        if self.kill_switch().trim().is_empty() {
+            errors.push(format!("{path}.kill_switch: must not be empty"));
+        }
+        if self.max_offers_per_hour == Some(0) {
+            errors.push(format!("{path}.max_offers_per_hour: must be at least 1"));
+        }
+        self.limits.validate(&format!("{path}.limits"), errors);
+        for (symbol, limits) in &self.symbols {
+            limits.validate(&format!("{path}.symbols.{symbol}"), errors);
+        }
+    }
+}
+
+// Shared limits and kill switch. Cheap to clone, all clones share the configuration and the
+// offers counted in the last hour.
+#[derive(Clone, Debug, Default)]
+pub struct Risk {
+    config: Arc<RwLock<Config>>,
+    // when offers were submitted, per `exchange account symbol` and under "" all of them
+    submitted: Arc<Mutex<HashMap<String, VecDeque<Instant>>>>,
+}
+
+impl Risk {
+    pub fn new(config: Config) -> Self {
+        Self {
+            config: Arc::new(RwLock::new(config)),
+            ..Default::default()
+        }
+    }
+
+    pub fn configure(&self, config: Config) {
+        *self.config.write().unwrap_or_else(|e| e.into_inner()) = config;
+    }
+
+    pub fn config(&self) -> RwLockReadGuard<'_, Config> {
+        self.config.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    // Why the kill switch was engaged, None while it is released. A kill switch file that
+    // cannot be read counts as engaged.
+    pub fn halted(&self) -> Option<String> {
+        let path = self.config().kill_switch().to_string();
+        match std::fs::read_to_string(&path) {
+            Ok(reason) if reason.trim().is_empty() => Some(format!("{path} exists")),
+            Ok(reason) => Some(reason.trim().to_string()),
+            Err(e) if e.kind() == ErrorKind::NotFound => None,
+            Err(e) => Some(format!("{path} is unreadable: {e}")),
+        }
+    }
+
+    pub fn engage(&self, reason: &str) -> Result<()> {
+        let path = self.config().kill_switch().to_string();
+        std::fs::write(&path, format!("{} {}\n", Utc::now().to_rfc3339(), reason))
+            .map_err(|err| anyhow!("failed to write kill switch {}: {:?}", path, err))
+    }
+
+    // Whether the kill switch was engaged
+    pub fn release(&self) -> Result<bool> {
+        let path = self.config().kill_switch().to_string();
+        match std::fs::remove_file(&path) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(anyhow!("failed to remove kill switch {}: {:?}", path, e)),
+        }
+    }
+
+    pub fn guard(
+        &self,
+        exchange: &'static str,
+        client: Arc<dyn lending::Api>,
+    ) -> Arc<dyn lending::Api> {
+        Arc::new(Guarded {
+            exchange,
+            client,
+            risk: self.clone(),
+        })
+    }
+
+    pub fn check_halted(&self) -> Result<()> {
+        match self.halted() {
+            Some(reason) => Err(Rejected::Halted(reason).into()),
+            None => Ok(()),
+        }
+    }
+
+    // Takes one of the offers allowed per hour, to be given back with `give_back` if the offer is
+    // not submitted after all. Counting and taking happen under one lock, so concurrent offers
+    // cannot exceed max_offers_per_hour together.
+    fn admit(
+        &self,
+        key: &str,
+        symbol: &str,
+        offer: (f64, f64, u32),
+        exposure: impl FnOnce() -> Result<Exposure>,
+    ) -> Result<Instant> {
+        self.check_halted()?;
+        let (global, limits) = {
+            let config = self.config();
+            (config.max_offers_per_hour, config.limits(symbol))
+        };
+
+        let now = Instant::now();
+        let mut submitted = self.submitted.lock().unwrap_or_else(|e| e.into_inner());
+        for (key, max, name) in [
+            ("", global, "risk.max_offers_per_hour"),
+            (key, limits.max_offers_per_hour, "max_offers_per_hour"),
+        ] {
+            let times = submitted.entry(key.to_string()).or_default();
+            while times
+                .front()
+                .is_some_and(|t| now.duration_since(*t) >= HOUR)
+            {
+                times.pop_front();
+            }
+            if let Some(max) = max.filter(|max| times.len() >= *max as usize) {
+                return Err(Rejected::Limit(format!(
+                    "{} offers submitted in the last hour, {name} is {max}",
+                    times.len()
+                ))
+                .into());
+            }
+        }
+        for key in ["", key] {
+            submitted.entry(key.to_string()).or_default().push_back(now);
+        }
+        drop(submitted);
+
+        let (amount, rate, period) = offer;
+        let checked = match limits.needs_exposure() {
+            true => exposure().map(Some),
+            false => Ok(None),
+        }
+        .and_then(|exposure| Ok(limits.check(amount, rate, period, exposure.as_ref())?));
+        if checked.is_err() {
+            self.give_back(key, now);
+        }
+        checked.map(|_| now)
+    }
+
+    fn give_back(&self, key: &str, at: Instant) {
+        let mut submitted = self.submitted.lock().unwrap_or_else(|e| e.into_inner());
+        for key in ["", key] {
+            if let Some(times) = submitted.get_mut(key) {
+                if let Some(i) = times.iter().rposition(|t| *t == at) {
+                    times.remove(i);
+                }
+            }
+        }
+    }
+}
+
+// Lending client checking every write against the limits and the kill switch
+#[derive(Debug)]
+struct Guarded {
+    exchange: &'static str,
+    client: Arc<dyn lending::Api>,
+    risk: Risk,
+}
+
+impl Guarded {
+    fn exposure(&self, symbol: &str) -> Result<Exposure> {
+        let placed: Vec<(f64, u32)> = self
+            .client
+            .credits(symbol)?
+            .iter()
+            .map(|c| (c.amount, c.period))
+            .chain(
+                self.client
+                    .active_offers(symbol)?
+                    .iter()
+                    .map(|o| (o.amount, o.period)),
+            )
+            .collect();
+
+        Ok(Exposure {
+            available: self.client.balance(symbol)?,
+            placed: placed.iter().map(|(amount, _)| amount).sum(),
+            long: placed
+                .iter()
+                .filter(|(_, period)| *period > LONG_PERIOD)
+                .map(|(amount, _)| amount)
+                .sum(),
+        })
+    }
+}
+
+impl lending::Api for Guarded {
+    fn account(&self) -> &str {
+        self.client.account()
+    }
+
+    fn info(&self, symbol: &str) -> Result<Info> {
+        self.client.info(symbol)
+    }
+
+    fn history(
+        &self,
+        symbol: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        limit: u32,
+    ) -> Result<Vec<Trade>> {
+        self.client.history(symbol, start, end, limit)
+    }
+
+    fn credit_history(&self, symbol: &str, since: Option<DateTime<Utc>>) -> Result<Vec<Credit>> {
+        self.client.credit_history(symbol, since)
+    }
+
+    fn credits(&self, symbol: &str) -> Result<Vec<Credit>> {
+        self.client.credits(symbol)
+    }
+
+    fn payouts(&self, symbol: &str, since: DateTime<Utc>) -> Result<Vec<Payout>> {
+        self.client.payouts(symbol, since)
+    }
+
+    fn balance(&self, symbol: &str) -> Result<f64> {
+        self.client.balance(symbol)
+    }
+
+    fn active_offers(&self, symbol: &str) -> Result<Vec<Offer>> {
+        self.client.active_offers(symbol)
+    }
+
+    fn submit_offer(
+        &self,
+        symbol: &str,
+        amount: f64,
+        rate: f64,
+        period: u32,
+    ) -> Result<OfferResponse> {
+        let key = format!("{} {} {}", self.exchange, self.client.account(), symbol);
+        let admitted = self.risk.admit(&key, symbol, (amount, rate, period), || {
+            self.exposure(symbol)
+        })?;
+        let response = self.client.submit_offer(symbol, amount, rate, period);
+        if response.is_err() {
+            self.risk.give_back(&key, admitted);
+        }
+        response
+    }
+
+    fn cancel_offer(&self, id: u32) -> Result<OfferResponse> {
+        self.risk.check_halted()?;
+        self.client.cancel_offer(id)
+    }
+
+    fn books(&self, symbol: &str) -> Result<Vec<Book>> {
+        self.client.books(symbol)
+    }
+
+    fn wallets(&self) -> Result<Vec<Wallet>> {
+        self.client.wallets()
+    }
+
+    fn transfer(
+        &self,
+        from: &str,
+        to: &str,
+        currency: &str,
+        amount: f64,
+    ) -> Result<TransferResponse> {
+        self.risk.check_halted()?;
+        self.client.transfer(from, to, currency, amount)
+    }
+
+    fn price(&self, currency: &str, quote: &str) -> Result<f64> {
+        self.client.price(currency, quote)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Exposure, Limits, Risk};
+    use std::collections::HashMap;
+
+    #[test]
+    fn enforce_limits() {
+        let config = Config {
+            max_offers_per_hour: Some(2),
+            limits: Limits {
+                max_offer: Some(500.),
+                min_rate: Some(0.0002),
+                ..Default::default()
+            },
+            symbols: HashMap::from([(
+                "fusd".to_string(),
+                Limits {
+                    max_lent: Some(1000.),
+                    max_long_share: Some(0.5),
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        let limits = config.limits("fUSD");
+        assert_eq!(limits.max_offer, Some(500.));
+        assert!(config.limits("fEUR").max_lent.is_none());
+
+        let exposure = Exposure {
+            available: 1000.,
+            placed: 600.,
+            long: 500.,
+        };
+        assert!(limits.check(200., 0.0003, 2, Some(&exposure)).is_ok());
+        assert!(limits.check(200., 0.0001, 2, Some(&exposure)).is_err());
+        assert!(limits.check(600., 0.0003, 2, Some(&exposure)).is_err());
+        assert!(limits.check(450., 0.0003, 2, Some(&exposure)).is_err());
+        // 700 of 1600 placed long
+        assert!(limits.check(200., 0.0003, 60, Some(&exposure)).is_ok());
+        assert!(limits.check(350., 0.0003, 60, Some(&exposure)).is_err());
+
+        let risk = Risk::new(Config {
+            kill_switch: Some("/nonexistent/tradebot.halt".into()),
+            ..config
+        });
+        let admit = |symbol: &str| {
+            risk.admit(
+                &format!("Bitfinex main {symbol}"),
+                symbol,
+                (200., 0.0003, 2),
+                || {
+                    Ok(Exposure {
+                        available: 1000.,
+                        placed: 0.,
+                        long: 0.,
+                    })
+                },
+            )
+        };
+        let admitted = admit("fUSD").unwrap();
+        admit("fEUR").unwrap();
+        assert!(admit("fUSD")
+            .unwrap_err()
+            .to_string()
+            .contains("risk.max_offers_per_hour"));
+        // a failed submission gives its offer back
+        risk.give_back("Bitfinex main fUSD", admitted);
+        admit("fUSD").unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+use anyhow::{anyhow, Result};
+use argon2::Argon2;
+use chacha20poly1305::aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use secrecy::{zeroize::Zeroizing, ExposeSecret, Secret};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::os::unix::fs::OpenOptionsExt;
+
+const PASSPHRASE_ENV: &str = "TRADEBOT_SECRETS_PASSPHRASE";
+// The service `keyring:NAME` entries are stored under in the OS keyring
+const KEYRING_SERVICE: &str = "tradebot";
+
+// An encrypted file holding the values referenced as `secrets:NAME`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub path: String,
+    // a plain value, `env:`, `file:` or `keyring:` reference, TRADEBOT_SECRETS_PASSPHRASE if omitted
+    pub passphrase: Option<Secret<String>>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    version: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+pub struct Store {
+    path: String,
+    passphrase: Secret<String>,
+    entries: BTreeMap<String, Secret<String>>,
+}
+
+impl Store {
+    // A missing file opens as an empty store, which is created on `save`.
+    pub fn open(config: &Config) -> Result<Self> {
+        let passphrase = match &config.passphrase {
+            Some(passphrase) => resolve_local(passphrase, &env)?,
+            None => std::env::var(PASSPHRASE_ENV)
+                .map(Secret::new)
+                .map_err(|_| {
+                    anyhow!(
+                        "no passphrase for {}, set secrets.passphrase or {}",
+                        config.path,
+                        PASSPHRASE_ENV
+                    )
+                })?,
+        };
+
+        let entries = match fs::read_to_string(&config.path) {
+            Ok(data) => decrypt(&data, &passphrase)
+                .map_err(|err| anyhow!("failed to open {}: {}", config.path, err))?,
+            Err(err) if err.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(err) => return Err(anyhow!("failed to read {}: {:?}", config.path, err)),
+        };
+
+        Ok(Self {
+            path: config.path.clone(),
+            passphrase,
+            entries,
+        })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Secret<String>> {
+        self.entries.get(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(|name| name.as_str())
+    }
+
+    pub fn set(&mut self, name: String, value: Secret<String>) {
+        self.entries.insert(name, value);
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.entries.remove(name).is_some()
+    }
+
+    // Encrypts with a fresh salt and nonce and replaces the file, readable by the owner only.
+    pub fn save(&self) -> Result<()> {
+        let data = encrypt(&self.entries, &self.passphrase)?;
+        let tmp = format!("{}.tmp", self.path);
+        fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&tmp)
+            .and_then(|mut file| file.write_all(data.as_bytes()))
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|err| anyhow!("failed to write {}: {:?}", self.path, err))
+    }
+}
+
+fn key(passphrase: &Secret<String>, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    let mut key = Zeroizing::new([0u8; 32]);
+    Argon2::default()
+        .hash_password_into(passphrase.expose_secret().as_bytes(), salt, key.as_mut())
+        .map_err(|err| anyhow!("failed to derive key: {}", err))?;
+    Ok(key)
+}
+
+fn encrypt(
+    entries: &BTreeMap<String, Secret<String>>,
+    passphrase: &Secret<String>,
+) -> Result<String> {
+    let plaintext = Zeroizing::new(serde_json::to_vec(
+        &entries
+            .iter()
+            .map(|(name, value)| (name, value.expose_secret()))
+            .collect::<BTreeMap<_, _>>(),
+    )?);
+
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    let cipher = XChaCha20Poly1305::new(key(passphrase, &salt)?.as_ref().into());
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext.as_slice())
+        .map_err(|_| anyhow!("failed to encrypt secrets"))?;
+
+    Ok(serde_json::to_string_pretty(&Envelope {
+        version: 1,
+        salt: hex::encode(salt),
+        nonce: hex::encode(nonce),
+        ciphertext: hex::encode(ciphertext),
+    })?)
+}
+
+fn decrypt(data: &str, passphrase: &Secret<String>) -> Result<BTreeMap<String, Secret<String>>> {
+    let envelope: Envelope = serde_json::from_str(data)?;
+    if envelope.version != 1 {
+        return Err(anyhow!("unsupported version {}", envelope.version));
+    }
+    let salt = hex::decode(envelope.salt)?;
+    let nonce = hex::decode(envelope.nonce)?;
+    let ciphertext = hex::decode(envelope.ciphertext)?;
+    if nonce.len() != 24 {
+        return Err(anyhow!("invalid nonce"));
+    }
+
+    let cipher = XChaCha20Poly1305::new(key(passphrase, &salt)?.as_ref().into());
+    let plaintext = Zeroizing::new(
+        cipher
+            .decrypt(XNonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| anyhow!("wrong passphrase or corrupted file"))?,
+    );
+    let entries: BTreeMap<String, String> = serde_json::from_slice(&plaintext)?;
+
+    Ok(entries
+        .into_iter()
+        .map(|(name, value)| (name, Secret::new(value)))
+        .collect())
+}
+
+fn env(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+// `env:NAME`, `file:PATH` and `keyring:NAME` references, anything else is taken as the value
+// itself
+fn resolve_local(
+    value: &Secret<String>,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<Secret<String>> {
+    let value = value.expose_secret();
+    if let Some(name) = value.strip_prefix("env:") {
+        env(name)
+            .map(Secret::new)
+            .ok_or_else(|| anyhow!("environment variable {} is not set", name))
+    } else if let Some(path) = value.strip_prefix("file:") {
+        fs::read_to_string(path)
+            .map(|s| Secret::new(s.trim_end_matches(['\r', '\n']).to_string()))
+            .map_err(|err| anyhow!("failed to read {}: {:?}", path, err))
+    } else if let Some(name) = value.strip_prefix("keyring:") {
+        keyring::Entry::new(KEYRING_SERVICE, name)
+            .and_then(|entry| entry.get_password())
+            .map(Secret::new)
+            .map_err(|err| anyhow!("failed to read {} from the keyring: {}", name, err))
+    } else {
+        Ok(Secret::new(value.to_string()))
+    }
+}
+
+// Resolves `env:`, `file:`, `keyring:` and `secrets:` references. The secrets file is opened on
+// the first `secrets:` reference only.
+pub struct Resolver<'a> {
+    config: Option<&'a Config>,
+    store: Option<std::result::Result<Store, String>>,
+    env: fn(&str) -> Option<String>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(config: Option<&'a Config>) -> Self {
+        Self {
+            config,
+            store: None,
+            env,
+        }
+    }
+
+    pub fn resolve(&mut self, value: &Secret<String>) -> Result<Secret<String>> {
+        let name = match value.expose_secret().strip_prefix("secrets:") {
+            Some(name) => name,
+            None => return resolve_local(value, &self.env),
+        };
+
+        let config = self.config.ok_or_else(|| {
+            anyhow!(
+                "secrets:{} is referenced but [secrets] is not configured",
+                name
+            )
+        })?;
+        let store = self
+            .store
+            .get_or_insert_with(|| Store::open(config).map_err(|err| err.to_string()))
+            .as_ref()
+            .map_err(|err| anyhow!("{}", err))?;
+        store
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("no secret named {} in {}", name, config.path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt, encrypt, Resolver};
+    use secrecy::{ExposeSecret, Secret};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn encrypt_and_resolve() {
+        let passphrase = Secret::new("correct horse".to_string());
+        let entries =
+            BTreeMap::from([("bfx_key".to_string(), Secret::new("plain key".to_string()))]);
+
+        let data = encrypt(&entries, &passphrase).unwrap();
+        assert!(!data.contains("plain key"));
+        let decrypted = decrypt(&data, &passphrase).unwrap();
+        assert_eq!(decrypted["bfx_key"].expose_secret(), "plain key");
+        assert!(decrypt(&data, &Secret::new("wrong".to_string())).is_err());
+
+        let mut resolver = Resolver {
+            env: |name| (name == "BFX_KEY").then(|| "from env".to_string()),
+            ..Resolver::new(None)
+        };
+        let resolved = resolver
+            .resolve(&Secret::new("env:BFX_KEY".to_string()))
+            .unwrap();
+        assert_eq!(resolved.expose_secret(), "from env");
+        assert!(resolver
+            .resolve(&Secret::new("env:BFX_SECRET".to_string()))
+            .is_err());
+        assert_eq!(
+            resolver
+                .resolve(&Secret::new("inline".to_string()))
+                .unwrap()
+                .expose_secret(),
+            "inline"
+        );
+        assert!(resolver
+            .resolve(&Secret::new("secrets:bfx_key".to_string()))
+            .is_err());
+    }
+}
@@ -1,23 +1,65 @@
-use crate::db::{DbConn, DbPool};
+use crate::db::decisions::{self, Decision};
+use crate::db::{candles::Timeframe, cursors, transfers, DbConn, DbPool};
+use crate::metrics;
+use crate::notify::{Kind, Notifier};
+use crate::risk::{Rejected, Risk};
+use crate::strategy::market::{self, Breaker, Sample};
+use crate::strategy::{Schedule, SharedStatus};
 use anyhow::{anyhow, Result};
 use chrono::{DateTime, Duration, Utc};
-use log::{debug, error, info};
-use rusqlite::params;
-use serde::Deserialize;
+use log::{debug, error, info, warn};
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 use std::sync::Arc;
 
+// trades per history request, the maximum accepted by Bitfinex
+const HISTORY_LIMIT: u32 = 10000;
+// history requests per sync; a larger backlog is caught up over the next syncs
+const HISTORY_PAGES: u32 = 10;
+
 pub struct Info {
     pub yield_lend: f64,
     pub duration_lend: f64,
 }
 
+#[derive(Serialize)]
 pub struct Offer {
     pub id: u32,
     pub symbol: String,
+    pub amount: f64,
     pub rate: f64,
+    pub period: u32,
     pub mts_created: DateTime<Utc>,
 }
 
+// what the exchange answered to submitting or cancelling an offer
+#[derive(Serialize)]
+pub struct OfferResponse {
+    pub id: u32,
+    pub status: String,
+    pub text: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct Wallet {
+    // exchange, margin or funding
+    pub kind: String,
+    pub currency: String,
+    pub balance: f64,
+    // None until the exchange has calculated it
+    pub available: Option<f64>,
+}
+
+// what the exchange answered to a transfer between wallets
+#[derive(Serialize)]
+pub struct TransferResponse {
+    pub status: String,
+    pub text: Option<String>,
+}
+
 pub struct Book {
     pub amount: f64,
     pub rate: f64,
@@ -25,12 +67,14 @@ pub struct Book {
 }
 
 pub struct Trade {
+    pub id: u32,
     pub mts: DateTime<Utc>,
     pub amount: f64,
     pub rate: f64,
     pub period: u32,
 }
 
+#[derive(Serialize)]
 pub struct Credit {
     pub id: u32,
     pub symbol: String,
@@ -44,20 +88,122 @@ pub struct Credit {
     pub position_pair: String,
 }
 
-pub trait Api: std::fmt::Debug {
+pub struct Payout {
+    pub id: u64,
+    pub mts: DateTime<Utc>,
+    pub amount: f64,
+}
+
+pub trait Api: std::fmt::Debug + Send + Sync {
+    fn account(&self) -> &str;
     fn info(&self, symbol: &str) -> Result<Info>;
-    fn history(&self, symbol: &str, start: DateTime<Utc>, end: DateTime<Utc>)
-        -> Result<Vec<Trade>>;
-    fn credit_history(&self, symbol: &str) -> Result<Vec<Credit>>;
+    // trades in [start, end], oldest first, at most `limit` of them
+    fn history(
+        &self,
+        symbol: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+        limit: u32,
+    ) -> Result<Vec<Trade>>;
+    // closed credits updated since `since`, or the most recent ones
+    fn credit_history(&self, symbol: &str, since: Option<DateTime<Utc>>) -> Result<Vec<Credit>>;
     fn credits(&self, symbol: &str) -> Result<Vec<Credit>>;
+    // interest paid out since `since`
+    fn payouts(&self, symbol: &str, since: DateTime<Utc>) -> Result<Vec<Payout>>;
     fn balance(&self, symbol: &str) -> Result<f64>;
     fn active_offers(&self, symbol: &str) -> Result<Vec<Offer>>;
-    fn submit_offer(&self, symbol: &str, amount: f64, rate: f64, period: u32) -> Result<()>;
-    fn cancel_offer(&self, id: u32) -> Result<()>;
+    fn submit_offer(
+        &self,
+        symbol: &str,
+        amount: f64,
+        rate: f64,
+        period: u32,
+    ) -> Result<OfferResponse>;
+    fn cancel_offer(&self, id: u32) -> Result<OfferResponse>;
     fn books(&self, symbol: &str) -> Result<Vec<Book>>;
+    fn wallets(&self) -> Result<Vec<Wallet>>;
+    // moves `amount` of `currency` between two wallets of the account
+    fn transfer(
+        &self,
+        from: &str,
+        to: &str,
+        currency: &str,
+        amount: f64,
+    ) -> Result<TransferResponse>;
+    // last traded price of `currency` in `quote`, e.g. BTC in USD
+    fn price(&self, currency: &str, quote: &str) -> Result<f64>;
+}
+
+// Balance above `reserve` may be lent to book offers meeting the thresholds of the tier
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tier {
+    pub reserve: f64,
+    // min_apy of the strategy if omitted
+    pub min_rate: Option<f64>,
+    // if omitted, offers are made for the longest period the rate is worth, see period_by_rate,
+    // otherwise for the period asked
+    pub max_period: Option<u32>,
+}
+
+// Idle balance of the other wallets is moved into the funding wallet before offers are made
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sweep {
+    // `exchange` and `margin`, only `exchange` if omitted. Margin balance backing open
+    // positions is not available and stays.
+    pub wallets: Option<Vec<String>>,
+    // balance left in each swept wallet, 0 if omitted
+    pub threshold: Option<f64>,
+}
+
+impl Sweep {
+    pub fn wallets(&self) -> Vec<String> {
+        self.wallets
+            .clone()
+            .unwrap_or_else(|| vec!["exchange".into()])
+    }
+
+    // What to move of a wallet's available balance, rounded down to 8 decimals
+    fn amount(&self, available: f64) -> Option<f64> {
+        let amount = ((available - self.threshold.unwrap_or(0.)) * 1e8).floor() / 1e8;
+        (amount > 0.).then_some(amount)
+    }
+
+    // The swept wallets of `currency` with something to move, and how much
+    fn moves<'a>(&self, currency: &str, wallets: &'a [Wallet]) -> Vec<(&'a Wallet, f64)> {
+        let swept = self.wallets();
+        wallets
+            .iter()
+            .filter(|w| w.currency == currency && swept.contains(&w.kind))
+            .filter_map(|w| Some((w, self.amount(w.available?)?)))
+            .collect()
+    }
+
+    fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        let wallets = self.wallets();
+        if wallets.is_empty() {
+            errors.push(format!("{path}.wallets: needs at least one wallet"));
+        }
+        for wallet in &wallets {
+            if !["exchange", "margin"].contains(&wallet.as_str()) {
+                errors.push(format!(
+                    "{path}.wallets: {wallet:?} is not one of exchange, margin"
+                ));
+            }
+        }
+        if let Some(threshold) = self.threshold {
+            if !(threshold.is_finite() && threshold >= 0.) {
+                errors.push(format!(
+                    "{path}.threshold: must not be negative, got {threshold}"
+                ));
+            }
+        }
+    }
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub symbol: String,
     pub lending_size: Option<f64>,
@@ -65,163 +211,853 @@ pub struct Config {
     pub max_apy: Option<f64>,
     pub reserved_amount_1: Option<f64>,
     pub reserved_amount_2: Option<f64>,
+    // by ascending reserve, replacing reserved_amount_1 and reserved_amount_2
+    pub tiers: Option<Vec<Tier>>,
+    pub sync_schedule: Option<Schedule>,
+    pub offer_schedule: Option<Schedule>,
+    pub market: Option<market::Config>,
+    pub sweep: Option<Sweep>,
+}
+
+impl Config {
+    pub fn min_apy(&self) -> f64 {
+        self.min_apy.unwrap_or(0.0003)
+    }
+
+    pub fn max_apy(&self) -> f64 {
+        self.max_apy.unwrap_or(0.00082)
+    }
+
+    // Without `tiers`, balance above reserved_amount_1 is lent for the longest period the rate
+    // is worth and balance above reserved_amount_2 for up to 5 days.
+    pub fn tiers(&self) -> Vec<Tier> {
+        self.tiers.clone().unwrap_or_else(|| {
+            vec![
+                Tier {
+                    reserve: self.reserved_amount_1.unwrap_or(1000.),
+                    min_rate: None,
+                    max_period: None,
+                },
+                Tier {
+                    reserve: self.reserved_amount_2.unwrap_or(1500.),
+                    min_rate: None,
+                    max_period: Some(5),
+                },
+            ]
+        })
+    }
+
+    // The first tier unlocked by `amount` admitting an offer at `rate` for `period` days, with
+    // the period to offer for
+    fn tier(&self, amount: f64, rate: f64, period: u32) -> Option<(Tier, u32)> {
+        let period_lim = period_by_rate(rate);
+        self.tiers().into_iter().find_map(|tier| {
+            let max_period = tier.max_period.unwrap_or(period_lim);
+            (amount > tier.reserve
+                && rate >= tier.min_rate.unwrap_or(self.min_apy())
+                && period <= max_period)
+                .then(|| {
+                    let period = tier.max_period.map_or(period_lim, |_| period);
+                    (tier, period)
+                })
+        })
+    }
+
+    // Whether the estimated rate is offered. Without `tiers` it is offered at any rate once the
+    // balance exceeds reserved_amount_1, as it was before tiers.
+    fn offers_estimate(&self, amount: f64, rate: f64) -> bool {
+        rate >= self.min_apy()
+            || (self.tiers.is_none() && amount > self.reserved_amount_1.unwrap_or(1000.))
+            || self.tier(amount, rate, period_by_rate(rate)).is_some()
+    }
+
+    // Why no tier admits the offer, for the decisions
+    fn untiered(&self, amount: f64, rate: f64) -> &'static str {
+        let unlocked: Vec<Tier> = self
+            .tiers()
+            .into_iter()
+            .filter(|tier| amount > tier.reserve)
+            .collect();
+        if unlocked.is_empty() {
+            "balance within reserve"
+        } else if unlocked
+            .iter()
+            .all(|tier| rate < tier.min_rate.unwrap_or(self.min_apy()))
+        {
+            "rate below min_rate of the unlocked tiers"
+        } else {
+            "period above max_period of the unlocked tiers"
+        }
+    }
+
+    pub fn market(&self) -> market::Config {
+        self.market.clone().unwrap_or_default()
+    }
+
+    // USD for fUSD
+    pub fn currency(&self) -> &str {
+        self.symbol.strip_prefix('f').unwrap_or(&self.symbol)
+    }
+
+    pub fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        if let Some(market) = &self.market {
+            market.validate(&format!("{path}.market"), errors);
+            let sync = self.sync_schedule.clone().unwrap_or_default().period();
+            if let (Some(minutes), Some(sync)) = (market.stale_minutes, sync) {
+                if u64::from(minutes) * 60 <= sync.as_secs() {
+                    errors.push(format!(
+                        "{path}.market.stale_minutes: must be longer than the sync interval of \
+                         {} minutes",
+                        sync.as_secs() / 60
+                    ));
+                }
+            }
+        }
+        if let Some(sweep) = &self.sweep {
+            sweep.validate(&format!("{path}.sweep"), errors);
+        }
+        if self.symbol.len() < 2 || !self.symbol.starts_with('f') {
+            errors.push(format!(
+                "{path}.symbol: {:?} is not a funding symbol such as fUSD",
+                self.symbol
+            ));
+        }
+        if let Some(size) = self.lending_size {
+            if !(size.is_finite() && size > 0.) {
+                errors.push(format!("{path}.lending_size: must be positive, got {size}"));
+            }
+        }
+        // daily rates, e.g. 0.0003 for 0.03% a day
+        for (key, rate) in [("min_apy", self.min_apy), ("max_apy", self.max_apy)] {
+            if let Some(rate) = rate {
+                if !(rate > 0. && rate < 0.1) {
+                    errors.push(format!(
+                        "{path}.{key}: must be a daily rate between 0 and 0.1, got {rate}"
+                    ));
+                }
+            }
+        }
+        if self.min_apy() > self.max_apy() {
+            errors.push(format!(
+                "{path}.min_apy: {} exceeds max_apy {}",
+                self.min_apy(),
+                self.max_apy()
+            ));
+        }
+        for (key, amount) in [
+            ("reserved_amount_1", self.reserved_amount_1),
+            ("reserved_amount_2", self.reserved_amount_2),
+        ] {
+            if let Some(amount) = amount {
+                if !(amount.is_finite() && amount >= 0.) {
+                    errors.push(format!("{path}.{key}: must not be negative, got {amount}"));
+                }
+            }
+        }
+        if let Some(tiers) = &self.tiers {
+            if self.reserved_amount_1.is_some() || self.reserved_amount_2.is_some() {
+                errors.push(format!(
+                    "{path}.tiers: replaces reserved_amount_1 and reserved_amount_2, remove them"
+                ));
+            }
+            if tiers.is_empty() {
+                errors.push(format!("{path}.tiers: needs at least one tier"));
+            }
+            for (idx, tier) in tiers.iter().enumerate() {
+                let path = format!("{path}.tiers[{idx}]");
+                if !(tier.reserve.is_finite() && tier.reserve >= 0.) {
+                    errors.push(format!(
+                        "{path}.reserve: must not be negative, got {}",
+                        tier.reserve
+                    ));
+                }
+                if idx > 0 && tier.reserve < tiers[idx - 1].reserve {
+                    errors.push(format!(
+                        "{path}.reserve: tiers must be listed by ascending reserve"
+                    ));
+                }
+                if let Some(rate) = tier.min_rate {
+                    if !(rate > 0. && rate < 0.1) {
+                        errors.push(format!(
+                            "{path}.min_rate: must be a daily rate between 0 and 0.1, got {rate}"
+                        ));
+                    }
+                }
+                if let Some(period) = tier.max_period {
+                    if !(2..=120).contains(&period) {
+                        errors.push(format!(
+                            "{path}.max_period: must be between 2 and 120 days, got {period}"
+                        ));
+                    }
+                }
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Strategy {
+    id: String,
+    // exchange, account and symbol, labelling the metrics of this instance
+    labels: [String; 3],
     client: Arc<dyn Api>,
-    db_connection: DbConn,
+    db_pool: DbPool,
     config: Config,
-    now: DateTime<Utc>,
-    last_tick: DateTime<Utc>,
+    // end of the trade history already stored
+    trades_mts: DateTime<Utc>,
+    // last update time of the credit history already stored
+    credits_mts: Option<DateTime<Utc>>,
+    status: SharedStatus,
+    notifier: Notifier,
+    // amount, rate and period of the credits provided at the last sync, unknown before the
+    // first one
+    provided: Option<HashMap<u32, (f64, f64, u32)>>,
+    // payouts up to here were already checked
+    payouts_mts: DateTime<Utc>,
+    // since when enough balance to lend is available
+    idle_since: Option<DateTime<Utc>>,
+    // start of the running exec step, grouping its decisions
+    tick: DateTime<Utc>,
+    // identifies the configuration decisions were made with
+    config_version: String,
+    breaker: Breaker,
 }
 
 impl Strategy {
     pub fn new(
+        id: String,
         client: Arc<crate::exchange::ExchangeApiClient>,
         db_pool: DbPool,
+        notifier: Notifier,
+        risk: &Risk,
         config: Config,
-    ) -> Self {
-        let now = Utc::now();
-        let last_tick = now - Duration::minutes(1);
-        let client = match client.as_ref() {
-            crate::exchange::ExchangeApiClient::Cex(_) => unimplemented!(),
-            crate::exchange::ExchangeApiClient::Bitfinex(client) => client.clone(),
-        };
+    ) -> Result<Self> {
+        let conn = db_pool
+            .get()
+            .map_err(|err| anyhow!("failed to get db connection: {:?}", err))?;
 
-        Self {
-            client,
-            db_connection: db_pool.get().unwrap(),
-            config,
-            now,
-            last_tick,
+        // resume from the stored cursor, or from the latest trade logged by an older version
+        let trades_mts = match cursors::get(&conn, &id, "trades.mts")? {
+            Some(mts) => Some(mts),
+            None => conn.query_row(
+                "SELECT MAX(mts) FROM trades WHERE symbol = ?1",
+                params![format!("f{}", config.symbol)],
+                |row| row.get(0),
+            )?,
         }
+        .unwrap_or_else(|| Utc::now() - Duration::minutes(1));
+        let credits_mts = cursors::get(&conn, &id, "credits.mts")?;
+        let provided = cursors::get::<String>(&conn, &id, "provided")?
+            .and_then(|provided| serde_json::from_str(&provided).ok());
+        let payouts_mts = cursors::get(&conn, &id, "payouts.mts")?.unwrap_or_else(Utc::now);
+        // an open circuit breaker stays open across reloads and restarts
+        let breaker = cursors::get::<String>(&conn, &id, "breaker")?
+            .and_then(|breaker| serde_json::from_str(&breaker).ok())
+            .unwrap_or_default();
+        debug!("[{}] resuming trades from {}", id, trades_mts);
+        let config_version = hex::encode(&Sha256::digest(format!("{:?}", config).as_bytes())[..6]);
+
+        Ok(Self {
+            id,
+            labels: [
+                client.name().to_string(),
+                client.account().to_string(),
+                config.symbol.clone(),
+            ],
+            client: client.lending(risk)?,
+            db_pool,
+            config,
+            trades_mts,
+            credits_mts,
+            status: SharedStatus::default(),
+            notifier,
+            provided,
+            payouts_mts,
+            idle_since: None,
+            tick: Utc::now(),
+            config_version,
+            breaker,
+        })
+    }
+
+    fn labels(&self) -> [&str; 3] {
+        [&self.labels[0], &self.labels[1], &self.labels[2]]
+    }
+
+    fn span(&self, step: &str) -> tracing::Span {
+        tracing::info_span!(
+            "step",
+            exchange = self.labels[0],
+            account = self.labels[1],
+            symbol = self.labels[2],
+            step
+        )
+    }
+
+    fn db(&self) -> Result<DbConn> {
+        self.db_pool
+            .get()
+            .map_err(|err| anyhow!("failed to get db connection: {:?}", err))
     }
 
-    pub fn log_history(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Result<()> {
+    // Stores trades from the cursor up to `end` page by page, moving the cursor along with
+    // each page so an interrupted sync does not lose what was already fetched.
+    pub fn log_history(&mut self, end: DateTime<Utc>) -> Result<()> {
         let symbol = self.config.symbol.as_str();
-        let history = self.client.history(symbol, start, end)?;
-        for h in &history {
-            self.db_connection
-                .execute(
-                    "INSERT INTO trades (symbol, mts, amount, rate, period)
-    VALUES (?1, ?2, ?3, ?4, ?5)",
-                    params![format!("f{symbol}"), &h.mts, &h.amount, &h.rate, &h.period],
-                )
+        let conn = self.db()?;
+
+        for _ in 0..HISTORY_PAGES {
+            let history = self
+                .client
+                .history(symbol, self.trades_mts, end, HISTORY_LIMIT)?;
+            let full = history.len() as u32 >= HISTORY_LIMIT;
+            // a full page continues from its last trade, which is fetched again and ignored
+            let cursor = match history.last() {
+                Some(last) if full && last.mts > self.trades_mts => last.mts,
+                Some(last) if full => last.mts + Duration::milliseconds(1),
+                _ => end,
+            };
+
+            let tx = conn.unchecked_transaction()?;
+            {
+                let mut stmt = tx.prepare_cached(
+                    "INSERT OR IGNORE INTO trades (symbol, mts, amount, rate, period, id)
+    VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                )?;
+                for h in &history {
+                    stmt.execute(params![
+                        format!("f{symbol}"),
+                        &h.mts,
+                        &h.amount,
+                        &h.rate,
+                        &h.period,
+                        &h.id
+                    ])
+                    .map_err(|err| anyhow!("failed to log history: {:?}", err))?;
+                }
+            }
+            cursors::set(&tx, &self.id, "trades.mts", cursor)?;
+            tx.commit()
                 .map_err(|err| anyhow!("failed to log history: {:?}", err))?;
+            self.trades_mts = cursor;
+
+            if !full {
+                return Ok(());
+            }
         }
 
+        info!(
+            "[{}] history caught up to {}, continuing on the next sync",
+            self.id, self.trades_mts
+        );
         Ok(())
     }
 
-    pub fn log_credits(&self) -> Result<()> {
+    pub fn log_credits(&mut self) -> Result<()> {
         let symbol = self.config.symbol.as_str();
-        let credits = self.client.credit_history(symbol)?;
-        for c in &credits {
-            self.db_connection
-                .execute(
-                    "INSERT OR REPLACE INTO credits VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-                    params![
-                        &c.id,
-                        &c.symbol,
-                        &c.amount,
-                        &c.rate,
-                        &c.period,
-                        &c.mts_opening,
-                        &c.mts_last_payout,
-                        &c.position_pair
-                    ],
-                )
+        let credits = self.client.credit_history(symbol, self.credits_mts)?;
+        let cursor = credits
+            .iter()
+            .map(|c| c.mts_update)
+            .max()
+            .max(self.credits_mts);
+
+        let conn = self.db()?;
+        let tx = conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR REPLACE INTO credits (
+                    id, symbol, amount, rate, period, opening, last_payout, position_pair,
+                    account
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for c in &credits {
+                stmt.execute(params![
+                    &c.id,
+                    &c.symbol,
+                    &c.amount,
+                    &c.rate,
+                    &c.period,
+                    &c.mts_opening,
+                    &c.mts_last_payout,
+                    &c.position_pair,
+                    self.client.account()
+                ])
                 .map_err(|err| anyhow!("failed to log credits: {:?}", err))?;
+            }
+        }
+        if let Some(cursor) = cursor {
+            cursors::set(&tx, &self.id, "credits.mts", cursor)?;
         }
+        tx.commit()
+            .map_err(|err| anyhow!("failed to log credits: {:?}", err))?;
+        self.credits_mts = cursor;
 
         Ok(())
     }
 
-    pub fn log_provided(&self) -> Result<()> {
+    pub fn log_provided(&mut self) -> Result<()> {
         let symbol = self.config.symbol.as_str();
         let credits = self.client.credits(symbol)?;
-        for c in &credits {
-            self.db_connection
-                .execute(
-                    "INSERT OR REPLACE INTO provided VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
-                    params![
-                        &c.id,
-                        &c.symbol,
-                        &c.mts_create,
-                        &c.mts_update,
-                        &c.amount,
-                        &c.rate,
-                        &c.period,
-                        &c.position_pair
-                    ],
-                )
+        metrics::BALANCE_LENT
+            .with_label_values(&self.labels())
+            .set(credits.iter().map(|c| c.amount).sum());
+        self.notify_credits(&credits);
+
+        let conn = self.db()?;
+        let tx = conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR REPLACE INTO provided (
+                    id, symbol, \"create\", \"update\", amount, rate, period, position_pair,
+                    account
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            )?;
+            for c in &credits {
+                stmt.execute(params![
+                    &c.id,
+                    &c.symbol,
+                    &c.mts_create,
+                    &c.mts_update,
+                    &c.amount,
+                    &c.rate,
+                    &c.period,
+                    &c.position_pair,
+                    self.client.account()
+                ])
                 .map_err(|err| anyhow!("failed to log provided: {:?}", err))?;
+            }
+        }
+        cursors::set(
+            &tx,
+            &self.id,
+            "provided",
+            serde_json::to_string(&self.provided)?,
+        )?;
+        tx.commit()
+            .map_err(|err| anyhow!("failed to log provided: {:?}", err))
+    }
+
+    // Reports credits opened or closed since the last sync.
+    fn notify_credits(&mut self, credits: &[Credit]) {
+        let current: HashMap<u32, (f64, f64, u32)> = credits
+            .iter()
+            .map(|c| (c.id, (c.amount, c.rate, c.period)))
+            .collect();
+        let previous = match self.provided.replace(current.clone()) {
+            Some(previous) => previous,
+            None => return,
+        };
+        let describe = |(amount, rate, period): &(f64, f64, u32)| {
+            format!(
+                "{:.2} {} at {:.4}% for {} days",
+                amount,
+                self.config.symbol,
+                rate * 100.,
+                period
+            )
+        };
+
+        for (id, credit) in current.iter().filter(|(id, _)| !previous.contains_key(id)) {
+            self.notifier.notify(
+                Kind::CreditOpened,
+                &self.id,
+                format!("credit {id} opened"),
+                describe(credit),
+            );
+        }
+        for (id, credit) in previous.iter().filter(|(id, _)| !current.contains_key(id)) {
+            self.notifier.notify(
+                Kind::CreditClosed,
+                &self.id,
+                format!("credit {id} closed"),
+                describe(credit),
+            );
+        }
+    }
+
+    fn notify_payouts(&mut self) -> Result<()> {
+        if !self.notifier.wants(Kind::LargePayout) {
+            return Ok(());
+        }
+        let payouts = self.client.payouts(&self.config.symbol, self.payouts_mts)?;
+        let threshold = self.notifier.config().large_payout();
+
+        for payout in payouts.iter().filter(|p| p.mts > self.payouts_mts) {
+            if payout.amount >= threshold {
+                self.notifier.notify(
+                    Kind::LargePayout,
+                    &self.id,
+                    format!("payout {}", payout.id),
+                    format!(
+                        "{:.2} {} paid out at {}",
+                        payout.amount, self.config.symbol, payout.mts
+                    ),
+                );
+            }
+        }
+        if let Some(mts) = payouts.iter().map(|p| p.mts).max() {
+            self.payouts_mts = self.payouts_mts.max(mts);
+            cursors::set(&*self.db()?, &self.id, "payouts.mts", self.payouts_mts)?;
         }
 
         Ok(())
     }
 
+    // Reports a balance large enough to lend that stayed unlent for `idle_hours`.
+    fn notify_idle(&mut self, balance: f64) {
+        let now = Utc::now();
+        if balance < self.config.lending_size.unwrap_or(200.0) {
+            self.idle_since = None;
+            return;
+        }
+
+        let since = *self.idle_since.get_or_insert(now);
+        let hours = self.notifier.config().idle_hours();
+        if now - since >= Duration::hours(hours.into()) {
+            self.notifier.notify(
+                Kind::IdleBalance,
+                &self.id,
+                "balance idle".into(),
+                format!(
+                    "{:.2} {} available and not lent for {} hours",
+                    balance,
+                    self.config.symbol,
+                    (now - since).num_hours()
+                ),
+            );
+        }
+    }
+
+    pub fn log_offers(&self) -> Result<()> {
+        let symbol = self.config.symbol.as_str();
+        let offers = self.client.active_offers(symbol)?;
+        let now = Utc::now();
+
+        let conn = self.db()?;
+        let tx = conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT OR REPLACE INTO offers (
+                    id, account, symbol, amount, rate, period, created, last_seen
+                ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            )?;
+            for o in &offers {
+                stmt.execute(params![
+                    &o.id,
+                    self.client.account(),
+                    &o.symbol,
+                    &o.amount,
+                    &o.rate,
+                    &o.period,
+                    &o.mts_created,
+                    &now
+                ])
+                .map_err(|err| anyhow!("failed to log offers: {:?}", err))?;
+            }
+        }
+        tx.commit()
+            .map_err(|err| anyhow!("failed to log offers: {:?}", err))
+    }
+
     fn get_rate(&self) -> Result<f64> {
+        let rate = estimate_rate(
+            &*self.db()?,
+            &self.config.symbol,
+            self.config.market().outlier_mads(),
+        )?;
+
+        self.status.write().set_rate(rate);
+        metrics::ESTIMATED_RATE
+            .with_label_values(&self.labels())
+            .set(rate);
+
+        Ok(rate)
+    }
+
+    // Why market data looks abnormal, None if it does not: trades are stale, or the estimated
+    // rate agrees with neither the book nor the funding stats.
+    fn check_market(&self, rate: f64, books: &[Book]) -> Result<Option<String>> {
         let symbol = self.config.symbol.as_str();
-        match self.db_connection.query_row(
-            "SELECT MAX(rate) * 0.8 + AVG(rate) * 0.2
-            FROM (
-                SELECT rate
-                FROM trades
-                WHERE
-                    symbol = ?1 AND
-                    DATETIME(mts) > DATETIME('now', '-12 hours')
-            )",
+        let market = self.config.market();
+        let sync = self
+            .config
+            .sync_schedule
+            .clone()
+            .unwrap_or_default()
+            .period();
+
+        let last_trade: Option<DateTime<Utc>> = self.db()?.query_row(
+            "SELECT MAX(mts) FROM trades WHERE symbol = ?1",
             params![format!("f{symbol}")],
             |row| row.get(0),
-        ) {
-            Ok(rate) => Ok(rate),
-            Err(_) => {
-                match self.db_connection.query_row(
-                    "SELECT MAX(rate) * 0.8 + AVG(rate) * 0.2
-                    FROM (
-                        SELECT rate
-                        FROM trades
-                        WHERE symbol = ?1
-                        ORDER BY mts DESC
-                        LIMIT 100
-                    )",
-                    params![format!("f{symbol}")],
-                    |row| row.get(0),
-                ) {
-                    Ok(rate) => Ok(rate),
-                    Err(e) => Err(anyhow!("failed to get rate: {:?}", e)),
-                }
+        )?;
+        match last_trade {
+            Some(mts) if Utc::now() - mts <= market.stale(sync) => {}
+            Some(mts) => {
+                return Ok(Some(format!(
+                    "no trades for {} minutes",
+                    (Utc::now() - mts).num_minutes()
+                )))
             }
+            None => return Ok(Some("no trades stored".into())),
         }
+
+        let best_ask = books
+            .iter()
+            .filter(|b| b.amount > 0.)
+            .map(|b| b.rate)
+            .reduce(f64::min);
+        let yield_lend = match self.client.info(symbol) {
+            Ok(info) => Some(info.yield_lend),
+            Err(e) => {
+                debug!("[{}] funding stats unavailable: {:?}", self.id, e);
+                None
+            }
+        };
+        let references: Vec<(&str, f64)> = [("book", best_ask), ("funding stats", yield_lend)]
+            .into_iter()
+            .filter_map(|(name, rate)| Some((name, rate?)))
+            .collect();
+
+        Ok(market::cross_check(rate, &references, market.max_ratio()).err())
     }
 
-    fn update_offer(&self) -> Result<()> {
-        let symbol = self.config.symbol.as_str();
+    // Feeds the market data checks to the circuit breaker, returns why offers are paused if
+    // they are.
+    fn circuit_breaker(&mut self, abnormal: Option<String>) -> Option<String> {
+        let was_open = self.breaker.reason().is_some();
+        // a closed breaker that stays closed has nothing to store
+        let store = was_open || abnormal.is_some();
+        let reason = self
+            .breaker
+            .update(abnormal, Utc::now(), self.config.market().cooldown())
+            .map(String::from);
+        if store {
+            let stored = serde_json::to_string(&self.breaker)
+                .map_err(|err| anyhow!(err))
+                .and_then(|breaker| cursors::set(&*self.db()?, &self.id, "breaker", breaker));
+            if let Err(e) = stored {
+                error!("[{}] failed to store the circuit breaker: {:?}", self.id, e);
+            }
+        }
 
-        // cancel offer if rate difference > 5% or creation time > 1 hours
-        let rate = self.get_rate()?;
+        match (&reason, was_open) {
+            (Some(reason), false) => {
+                warn!("[{}] circuit breaker open: {}", self.id, reason);
+                self.notifier.notify(
+                    Kind::CircuitBreaker,
+                    &self.id,
+                    "offers paused".into(),
+                    format!("market data looks abnormal: {reason}"),
+                );
+            }
+            (None, true) => {
+                info!("[{}] circuit breaker closed", self.id);
+                self.notifier.notify(
+                    Kind::CircuitBreaker,
+                    &self.id,
+                    "offers resumed".into(),
+                    "market data looks normal again".into(),
+                );
+            }
+            _ => {}
+        }
+        self.status.write().circuit_breaker = reason.clone();
+        metrics::CIRCUIT_BREAKER
+            .with_label_values(&self.labels())
+            .set(if reason.is_some() { 1. } else { 0. });
 
-        for offer in self.client.active_offers(symbol)? {
-            if (rate - offer.rate).abs() / rate > 0.05
-                && (self.last_tick - offer.mts_created) > Duration::hours(1)
+        reason
+    }
+
+    // A decision of the running tick, to be completed by the caller
+    fn decision(&self, action: &str, reason: String) -> Decision {
+        Decision {
+            tick: self.tick,
+            mts: Utc::now(),
+            exchange: self.labels[0].clone(),
+            account: self.labels[1].clone(),
+            symbol: self.labels[2].clone(),
+            action: action.into(),
+            reason,
+            ..Default::default()
+        }
+    }
+
+    // Failing to store a decision is logged and does not stop trading.
+    fn record(&self, decision: Decision) {
+        if let Err(e) = self
+            .db()
+            .and_then(|conn| decisions::insert(&conn, &decision))
+        {
+            error!("[{}] {:?}", self.id, e);
+        }
+    }
+
+    fn record_response(&self, decision: Decision, result: &Result<OfferResponse>) {
+        self.record(match result {
+            Ok(response) => Decision {
+                offer_id: decision.offer_id.or(Some(response.id)),
+                status: Some(response.status.clone()),
+                text: response.text.clone(),
+                ..decision
+            },
+            Err(e) => match e.downcast_ref::<Rejected>() {
+                Some(rejected) => Decision {
+                    status: Some("REJECTED".into()),
+                    text: Some(rejected.reason()),
+                    ..decision
+                },
+                None => Decision {
+                    status: Some("ERROR".into()),
+                    text: Some(format!("{e}")),
+                    ..decision
+                },
+            },
+        });
+    }
+
+    // Moves the available balance above the threshold of the swept wallets into the funding
+    // wallet. Failed moves are logged and recorded, offers are still made from what is there.
+    fn sweep(&self) {
+        let Some(sweep) = &self.config.sweep else {
+            return;
+        };
+        let currency = self.config.currency();
+        let wallets = match self.client.wallets() {
+            Ok(wallets) => wallets,
+            Err(e) => {
+                error!("[{}] failed to get wallets: {:?}", self.id, e);
+                return;
+            }
+        };
+        for (wallet, amount) in sweep.moves(currency, &wallets) {
+            let result = self
+                .client
+                .transfer(&wallet.kind, "funding", currency, amount);
+            let (status, text) = match &result {
+                Ok(response) => (response.status.clone(), response.text.clone()),
+                Err(e) => match e.downcast_ref::<Rejected>() {
+                    Some(rejected) => ("REJECTED".into(), Some(rejected.reason())),
+                    None => ("ERROR".into(), Some(format!("{e}"))),
+                },
+            };
+            match &result {
+                Ok(_) => info!(
+                    "[{}] moved {} {} from {} to funding",
+                    self.id, amount, currency, wallet.kind
+                ),
+                Err(e) => error!(
+                    "[{}] failed to move {} {} from {}: {:?}",
+                    self.id, amount, currency, wallet.kind, e
+                ),
+            }
+            let transfer = transfers::Transfer {
+                mts: Utc::now(),
+                exchange: self.labels[0].clone(),
+                account: self.labels[1].clone(),
+                currency: currency.into(),
+                from_wallet: wallet.kind.clone(),
+                to_wallet: "funding".into(),
+                amount,
+                reason: "sweep".into(),
+                status,
+                text,
+                ..Default::default()
+            };
+            if let Err(e) = self
+                .db()
+                .and_then(|conn| transfers::insert(&conn, &transfer))
             {
-                self.client.cancel_offer(offer.id)?;
+                error!("[{}] {:?}", self.id, e);
             }
         }
+    }
 
+    // Whether the offer was placed. An offer refused by a limit is recorded and passed over,
+    // the next one may be within the limits.
+    fn submit(&self, amount: f64, rate: f64, period: u32, reason: &str) -> Result<bool> {
+        let result = self
+            .client
+            .submit_offer(&self.config.symbol, amount, rate, period);
+        self.record_response(
+            Decision {
+                amount: Some(amount),
+                rate: Some(rate),
+                period: Some(period),
+                ..self.decision("submit", reason.into())
+            },
+            &result,
+        );
+        if let Err(e) = &result {
+            if let Some(Rejected::Limit(_)) = e.downcast_ref::<Rejected>() {
+                warn!("[{}] {}", self.id, e);
+                return Ok(false);
+            }
+        }
+        result?;
+        metrics::OFFERS_SUBMITTED
+            .with_label_values(&self.labels())
+            .inc();
+        self.status.write().decide(
+            "submit",
+            format!(
+                "{:.2} at {:.4}% for {} days, {}",
+                amount,
+                rate * 100.,
+                period,
+                reason
+            ),
+        );
+        Ok(true)
+    }
+
+    fn cancel(&self, offer: &Offer, reason: &str) -> Result<()> {
+        let result = self.client.cancel_offer(offer.id);
+        self.record_response(
+            Decision {
+                amount: Some(offer.amount),
+                rate: Some(offer.rate),
+                period: Some(offer.period),
+                offer_id: Some(offer.id),
+                ..self.decision("cancel", reason.into())
+            },
+            &result,
+        );
+        result?;
+        metrics::OFFERS_CANCELLED
+            .with_label_values(&self.labels())
+            .inc();
+        self.status.write().decide(
+            "cancel",
+            format!(
+                "offer {} of {:.2} at {:.4}%, {}",
+                offer.id,
+                offer.amount,
+                offer.rate * 100.,
+                reason
+            ),
+        );
         Ok(())
     }
 
-    fn get_fair_offer_pair(&self) -> Result<Vec<(f64, u32)>> {
-        let symbol = self.config.symbol.as_str();
+    fn update_offer(&self, rate: f64, offers: Vec<Offer>) -> Result<()> {
+        // cancel offer if rate difference > 5% or creation time > 1 hours
 
-        let rate = self.get_rate()?;
+        for offer in offers {
+            if (rate - offer.rate).abs() / rate > 0.05
+                && (Utc::now() - offer.mts_created) > Duration::hours(1)
+            {
+                self.cancel(&offer, &format!("rate moved to {:.4}%", rate * 100.))?;
+            }
+        }
 
-        let mut offer_pair: Vec<(f64, u32)> = self
-            .client
-            .books(symbol)?
+        Ok(())
+    }
+
+    fn get_fair_offer_pair(&self, books: &[Book], rate: f64) -> Vec<(f64, u32)> {
+        let mut offer_pair: Vec<(f64, u32)> = books
             .iter()
             .filter_map(|b| {
                 if b.amount < 0.
@@ -235,67 +1071,149 @@ impl Strategy {
             .collect();
         offer_pair.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
 
-        Ok(offer_pair)
+        offer_pair
     }
 
-    fn submit_offer(&self) -> Result<()> {
-        let symbol = self.config.symbol.as_str();
-
-        self.update_offer()?;
+    fn submit_offer(&mut self) -> Result<()> {
+        let symbol = self.config.symbol.clone();
+        let symbol = symbol.as_str();
 
         let lend_unit_amount = self.config.lending_size.unwrap_or(200.0);
-        let min_lend_rate = self.config.min_apy.unwrap_or(0.0003);
-        let max_lend_rate = self.config.max_apy.unwrap_or(0.00082);
-        let preserved_amoount = self.config.reserved_amount_1.unwrap_or(1000.0);
-        let preserved_amoount_l2 = self.config.reserved_amount_1.unwrap_or(1500.0);
-
-        if let Ok(ba) = self.client.balance(symbol) {
-            log::debug!("balance available: {}", ba);
-            if ba >= lend_unit_amount {
-                let mut amount = (ba * 100.0).floor() / 100.0;
-
-                let rate = self.get_rate()?;
-                let period = period_by_rate(rate);
-
-                // sumit offer by calculated rate
-                if rate >= min_lend_rate || amount > preserved_amoount {
-                    self.client
-                        .submit_offer(symbol, lend_unit_amount, rate, period)?;
-                    amount -= lend_unit_amount;
+        let max_lend_rate = self.config.max_apy();
+
+        let balance = self.client.balance(symbol);
+        let offers = self.client.active_offers(symbol)?;
+        if let Ok(ba) = balance {
+            metrics::BALANCE_AVAILABLE
+                .with_label_values(&self.labels())
+                .set(ba);
+            // nothing to reprice and too little to lend, the market is not looked at
+            if ba < lend_unit_amount && offers.is_empty() {
+                debug!("[{}] balance {:.2} below lending size", self.id, ba);
+                return Ok(());
+            }
+        }
+
+        let rate = self.get_rate()?;
+        let books = self.client.books(symbol)?;
+        let abnormal = self.check_market(rate, &books)?;
+        self.record_inputs(balance.as_ref().ok().copied(), rate, &books);
+        if let Some(reason) = self.circuit_breaker(abnormal) {
+            self.record(self.decision("skip", format!("circuit breaker open: {reason}")));
+            return Ok(());
+        }
+
+        self.update_offer(rate, offers)?;
+
+        let ba = match balance {
+            Ok(ba) => ba,
+            Err(e) => {
+                self.record(self.decision("skip", format!("balance unavailable: {e}")));
+                return Ok(());
+            }
+        };
+        log::debug!("balance available: {}", ba);
+        if ba < lend_unit_amount {
+            self.record(self.decision(
+                "skip",
+                format!("balance {ba:.2} below lending size {lend_unit_amount}"),
+            ));
+            return Ok(());
+        }
+        let mut amount = (ba * 100.0).floor() / 100.0;
+        let period = period_by_rate(rate);
+
+        // sumit offer by calculated rate
+        if self.config.offers_estimate(amount, rate) {
+            if self.submit(lend_unit_amount, rate, period, "estimated rate")? {
+                amount -= lend_unit_amount;
+            }
+        } else {
+            self.record(Decision {
+                rate: Some(rate),
+                period: Some(period),
+                ..self.decision(
+                    "skip",
+                    format!(
+                        "estimated rate below min_apy, {}",
+                        self.config.untiered(amount, rate)
+                    ),
+                )
+            });
+        }
+
+        // submit if fair offer found, book offers passed over are recorded once per reason
+        let mut skipped: Vec<(&str, usize)> = vec![];
+        for (b_rate, b_period) in self.get_fair_offer_pair(&books, rate) {
+            let period_lim = period_by_rate(b_rate);
+
+            let offer = if amount <= lend_unit_amount {
+                Err("remaining balance below lending size")
+            } else if b_rate > max_lend_rate {
+                Ok((period_lim, "book rate above max_apy".to_string()))
+            } else {
+                match self.config.tier(amount, b_rate, b_period) {
+                    Some((tier, period)) => Ok((
+                        period,
+                        format!("matching the book above reserve {}", tier.reserve),
+                    )),
+                    None => Err(self.config.untiered(amount, b_rate)),
                 }
+            };
 
-                // submit if fair offer found
-                for (b_rate, b_period) in self.get_fair_offer_pair()? {
-                    let period_lim = period_by_rate(b_rate);
-
-                    if amount > lend_unit_amount
-                        && (b_rate > max_lend_rate
-                            || (amount > preserved_amoount
-                                && period_lim >= b_period
-                                && b_rate >= min_lend_rate))
-                    {
-                        self.client
-                            .submit_offer(symbol, lend_unit_amount, b_rate, period_lim)?;
-                        amount -= lend_unit_amount;
-                    } else if amount > preserved_amoount_l2
-                        && b_period <= 5
-                        && b_rate >= min_lend_rate
-                    {
-                        self.client
-                            .submit_offer(symbol, lend_unit_amount, b_rate, b_period)?;
+            match offer {
+                Ok((period, reason)) => {
+                    if self.submit(lend_unit_amount, b_rate, period, &reason)? {
                         amount -= lend_unit_amount;
-                    } else {
-                        debug!(
+                    }
+                }
+                Err(reason) => {
+                    debug!(
                         "condition not met for (avail, rate, period, period_lim) = ({:.2}, {:.4}, {}, {})",
                         amount, b_rate * 100.0, b_period, period_lim
                     );
+                    match skipped.iter_mut().find(|(r, _)| *r == reason) {
+                        Some((_, count)) => *count += 1,
+                        None => skipped.push((reason, 1)),
                     }
                 }
             }
         }
+        for (reason, count) in skipped {
+            self.record(self.decision("skip", format!("{reason} ({count} book offers)")));
+        }
 
         Ok(())
     }
+
+    fn record_inputs(&self, balance: Option<f64>, rate: f64, books: &[Book]) {
+        let side = |bids: bool| {
+            let offers: Vec<&Book> = books.iter().filter(|b| (b.amount < 0.) == bids).collect();
+            let rates = offers.iter().map(|b| b.rate);
+            json!({
+                "count": offers.len(),
+                "amount": offers.iter().map(|b| b.amount.abs()).sum::<f64>(),
+                "best_rate": if bids {
+                    rates.reduce(f64::max)
+                } else {
+                    rates.reduce(f64::min)
+                },
+            })
+        };
+        let inputs = json!({
+            "balance": balance,
+            "rate": rate,
+            "book": { "bids": side(true), "asks": side(false) },
+            "config_version": self.config_version,
+        });
+
+        self.record(Decision {
+            rate: Some(rate),
+            amount: balance,
+            inputs: Some(inputs.to_string()),
+            ..self.decision("inputs", "tick started".into())
+        });
+    }
 }
 
 fn period_by_rate(rate: f64) -> u32 {
@@ -313,19 +1231,28 @@ fn period_by_rate(rate: f64) -> u32 {
 }
 
 impl super::Strategy for Strategy {
-    fn exec(&mut self) -> Result<()> {
-        if self.log_history(self.last_tick, self.now).is_ok() {
-            self.last_tick = self.now;
-            self.now += Duration::minutes(1);
-        } else {
-            error!("History fetch error");
-        };
+    fn sync(&mut self) -> Result<()> {
+        let _span = self.span("sync").entered();
+        if let Err(e) = self.log_history(Utc::now()) {
+            error!("[{}] failed to sync history: {:?}", self.id, e);
+        }
 
-        self.submit_offer()?;
         self.log_credits()?;
         self.log_provided()?;
+        self.log_offers()?;
 
         let info = self.client.info(self.config.symbol.clone().as_str())?;
+        metrics::YIELD_LEND
+            .with_label_values(&self.labels())
+            .set(info.yield_lend);
+        let balance = self.client.balance(&self.config.symbol)?;
+        metrics::BALANCE_AVAILABLE
+            .with_label_values(&self.labels())
+            .set(balance);
+        self.notify_idle(balance);
+        if let Err(e) = self.notify_payouts() {
+            error!("[{}] failed to check payouts: {:?}", self.id, e);
+        }
         let rate = self.get_rate()?;
         info!(
             "{} => (rate, r_3h, dur) = ({:.4}, {:.4}, {:.0})",
@@ -337,4 +1264,164 @@ impl super::Strategy for Strategy {
 
         Ok(())
     }
+
+    fn exec(&mut self) -> Result<()> {
+        let _span = self.span("exec").entered();
+        self.tick = Utc::now();
+        self.sweep();
+        // a write refused by the kill switch ends the tick, it is not a failure
+        match self.submit_offer() {
+            Err(e) if e.is::<Rejected>() => {
+                warn!("[{}] {}", self.id, e);
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    fn cancel_offers(&mut self) -> Result<()> {
+        let _span = self.span("cancel_offers").entered();
+        self.tick = Utc::now();
+        for offer in self.client.active_offers(&self.config.symbol)? {
+            self.cancel(&offer, "shutting down")?;
+        }
+        Ok(())
+    }
+
+    fn status(&self) -> SharedStatus {
+        self.status.clone()
+    }
+}
+
+// Estimated from the minute candles of the last 12 hours, or the last 100 trades without any,
+// leaving out samples more than `outlier_mads` deviations from the median.
+pub fn estimate_rate(conn: &Connection, symbol: &str, outlier_mads: f64) -> Result<f64> {
+    let query = |sql: &str, params: &[&dyn rusqlite::ToSql]| -> Result<Vec<Sample>> {
+        conn.prepare_cached(sql)?
+            .query_map(params, |row| {
+                Ok(Sample {
+                    high: row.get(0)?,
+                    rate_sum: row.get(1)?,
+                    count: row.get(2)?,
+                })
+            })?
+            .collect::<rusqlite::Result<_>>()
+            .map_err(|err| anyhow!("failed to get rate: {:?}", err))
+    };
+    let mut samples = query(
+        "SELECT high, rate_sum, count
+        FROM candles
+        WHERE
+            symbol = ?1 AND
+            timeframe = ?2 AND
+            mts > DATETIME('now', '-12 hours')",
+        params![format!("f{symbol}"), Timeframe::Minute.as_str()],
+    )?;
+    if samples.is_empty() {
+        samples = query(
+            "SELECT rate, rate, 1
+            FROM trades
+            WHERE symbol = ?1
+            ORDER BY mts DESC
+            LIMIT 100",
+            params![format!("f{symbol}")],
+        )?;
+    }
+    let count = samples.len();
+    let samples = market::without_outliers(samples, outlier_mads);
+    if samples.len() < count {
+        debug!(
+            "{}: left out {} outliers of {} samples",
+            symbol,
+            count - samples.len(),
+            count
+        );
+    }
+
+    market::estimate(&samples).ok_or_else(|| anyhow!("failed to get rate: no trades of {}", symbol))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Config, Sweep, Wallet};
+    use serde_json::json;
+
+    #[test]
+    fn pick_reserve_tier() {
+        let legacy: Config = serde_json::from_value(json!({
+            "symbol": "fUSD",
+            "min_apy": 0.0003,
+            "reserved_amount_1": 1000.,
+            "reserved_amount_2": 3000.,
+        }))
+        .unwrap();
+        // above the first reserve only periods up to the limit for the rate are lent
+        assert_eq!(legacy.tier(2000., 0.00045, 10).unwrap().1, 11);
+        assert!(legacy.tier(2000., 0.00045, 30).is_none());
+        // the short period tier unlocks above reserved_amount_2, not reserved_amount_1
+        assert!(legacy.tier(2000., 0.0003, 4).is_none());
+        assert_eq!(legacy.tier(3500., 0.0003, 4).unwrap().1, 4);
+        assert_eq!(legacy.untiered(500., 0.0003), "balance within reserve");
+        // the estimated rate has no floor above the first reserve
+        assert!(legacy.offers_estimate(2000., 0.0001));
+        assert!(!legacy.offers_estimate(500., 0.0001));
+
+        let tiered: Config = serde_json::from_value(json!({
+            "symbol": "fUSD",
+            "min_apy": 0.0003,
+            "tiers": [
+                { "reserve": 500. },
+                { "reserve": 5000., "min_rate": 0.0001, "max_period": 30 },
+            ],
+        }))
+        .unwrap();
+        assert!(tiered.tier(2000., 0.0002, 10).is_none());
+        assert!(!tiered.offers_estimate(2000., 0.0002));
+        assert_eq!(
+            tiered.untiered(2000., 0.0002),
+            "rate below min_rate of the unlocked tiers"
+        );
+        let (tier, period) = tiered.tier(6000., 0.0002, 10).unwrap();
+        assert_eq!((tier.reserve, period), (5000., 10));
+    }
+
+    #[test]
+    fn sweep_wallets() {
+        let wallet = |kind: &str, currency: &str, available| Wallet {
+            kind: kind.into(),
+            currency: currency.into(),
+            balance: 100.,
+            available,
+        };
+        let wallets = vec![
+            wallet("exchange", "USD", Some(100.123456789)),
+            wallet("margin", "USD", Some(50.)),
+            wallet("funding", "USD", Some(80.)),
+            wallet("exchange", "BTC", Some(1.)),
+            wallet("margin", "USD", None),
+        ];
+        let sweep = |wallets: Option<Vec<&str>>, threshold| Sweep {
+            wallets: wallets.map(|w| w.into_iter().map(String::from).collect()),
+            threshold,
+        };
+        let moves = |sweep: &Sweep| -> Vec<(String, f64)> {
+            sweep
+                .moves("USD", &wallets)
+                .into_iter()
+                .map(|(w, amount)| (w.kind.clone(), amount))
+                .collect()
+        };
+
+        // only the exchange wallet by default, rounded down to 8 decimals
+        assert_eq!(
+            moves(&sweep(None, None)),
+            vec![("exchange".to_string(), 100.12345678)]
+        );
+        // the threshold stays, a wallet at or below it is left alone
+        assert_eq!(
+            moves(&sweep(Some(vec!["exchange", "margin"]), Some(50.))),
+            vec![("exchange".to_string(), 50.12345678)]
+        );
+        assert_eq!(sweep(None, Some(100.2)).amount(100.123456789), None);
+    }
 }
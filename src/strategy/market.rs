@@ -0,0 +1,222 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+
+// scales the median absolute deviation to the standard deviation of normal data
+const MAD_SCALE: f64 = 1.4826;
+
+// `market` of a lending strategy, the checks market data has to pass before offers are made
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // samples further than this many deviations from the median are ignored, 5 if omitted
+    pub outlier_mads: Option<f64>,
+    // market data is stale without a trade for this long. Trades are stored by sync, so it has
+    // to be longer than the sync interval; 30 or two sync intervals if omitted, whichever is
+    // longer.
+    pub stale_minutes: Option<u32>,
+    // the estimated rate may be at most this many times above or below the book and the
+    // funding stats, 3 if omitted
+    pub max_ratio: Option<f64>,
+    // offers stay paused this long after market data last looked abnormal, 15 if omitted
+    pub cooldown_minutes: Option<u32>,
+}
+
+impl Config {
+    pub fn outlier_mads(&self) -> f64 {
+        self.outlier_mads.unwrap_or(5.)
+    }
+
+    pub fn stale(&self, sync: Option<std::time::Duration>) -> Duration {
+        match self.stale_minutes {
+            Some(minutes) => Duration::minutes(minutes.into()),
+            None => sync
+                .and_then(|sync| Duration::from_std(sync * 2).ok())
+                .map_or(Duration::minutes(30), |syncs| {
+                    syncs.max(Duration::minutes(30))
+                }),
+        }
+    }
+
+    pub fn max_ratio(&self) -> f64 {
+        self.max_ratio.unwrap_or(3.)
+    }
+
+    pub fn cooldown(&self) -> Duration {
+        Duration::minutes(self.cooldown_minutes.unwrap_or(15).into())
+    }
+
+    pub fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        if self
+            .outlier_mads
+            .is_some_and(|mads| !(mads.is_finite() && mads > 0.))
+        {
+            errors.push(format!("{path}.outlier_mads: must be positive"));
+        }
+        if self.stale_minutes == Some(0) {
+            errors.push(format!("{path}.stale_minutes: must be at least 1"));
+        }
+        if self
+            .max_ratio
+            .is_some_and(|ratio| !(ratio.is_finite() && ratio > 1.))
+        {
+            errors.push(format!("{path}.max_ratio: must be above 1"));
+        }
+    }
+}
+
+// Trades of a minute candle, or a single trade
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sample {
+    pub high: f64,
+    pub rate_sum: f64,
+    pub count: u32,
+}
+
+fn median(values: &mut [f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let len = values.len();
+    Some((values[(len - 1) / 2] + values[len / 2]) / 2.)
+}
+
+// Drops samples whose high is more than `mads` median absolute deviations from the median.
+// The deviation is at least 1% of the median, so that a market trading at nearly one rate does
+// not turn every other rate into an outlier.
+pub fn without_outliers(samples: Vec<Sample>, mads: f64) -> Vec<Sample> {
+    let mut highs: Vec<f64> = samples.iter().map(|s| s.high).collect();
+    let Some(center) = median(&mut highs) else {
+        return samples;
+    };
+    let mut deviations: Vec<f64> = highs.iter().map(|h| (h - center).abs()).collect();
+    let mad = median(&mut deviations)
+        .unwrap_or_default()
+        .max(center.abs() * 0.01);
+
+    samples
+        .into_iter()
+        .filter(|s| (s.high - center).abs() <= mads * MAD_SCALE * mad)
+        .collect()
+}
+
+// The highest rate weighted 0.8 and the average rate 0.2, None without samples
+pub fn estimate(samples: &[Sample]) -> Option<f64> {
+    let high = samples.iter().map(|s| s.high).reduce(f64::max)?;
+    let count: u32 = samples.iter().map(|s| s.count).sum();
+    if count == 0 {
+        return None;
+    }
+    let average = samples.iter().map(|s| s.rate_sum).sum::<f64>() / count as f64;
+
+    Some(high * 0.8 + average * 0.2)
+}
+
+// Whether `rate` is within `max_ratio` of at least one of the references, which are named for
+// the error. Without references there is nothing to check against.
+pub fn cross_check(
+    rate: f64,
+    references: &[(&str, f64)],
+    max_ratio: f64,
+) -> std::result::Result<(), String> {
+    let references: Vec<&(&str, f64)> = references.iter().filter(|(_, r)| *r > 0.).collect();
+    if references.is_empty()
+        || references
+            .iter()
+            .any(|(_, r)| rate <= r * max_ratio && rate >= r / max_ratio)
+    {
+        return Ok(());
+    }
+
+    Err(format!(
+        "estimated rate {:.4}% is off {}",
+        rate * 100.,
+        references
+            .iter()
+            .map(|(name, r)| format!("{name} {:.4}%", r * 100.))
+            .collect::<Vec<_>>()
+            .join(" and ")
+    ))
+}
+
+// Pauses offers while market data looks abnormal and for a cooldown after it last did
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Breaker {
+    // until when and why
+    open: Option<(DateTime<Utc>, String)>,
+}
+
+impl Breaker {
+    // Takes the outcome of the latest checks, returns why offers are paused if they are.
+    pub fn update(
+        &mut self,
+        abnormal: Option<String>,
+        now: DateTime<Utc>,
+        cooldown: Duration,
+    ) -> Option<&str> {
+        match abnormal {
+            Some(reason) => self.open = Some((now + cooldown, reason)),
+            None if self.open.as_ref().is_some_and(|(until, _)| *until <= now) => self.open = None,
+            None => {}
+        }
+        self.reason()
+    }
+
+    pub fn reason(&self) -> Option<&str> {
+        self.open.as_ref().map(|(_, reason)| reason.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cross_check, estimate, without_outliers, Breaker, Config, Sample};
+    use chrono::{Duration, Utc};
+
+    #[test]
+    fn filter_outliers_and_trip_breaker() {
+        let trade = |rate| Sample {
+            high: rate,
+            rate_sum: rate,
+            count: 1,
+        };
+        let mut samples: Vec<Sample> = (0..20).map(|i| trade(0.0002 + i as f64 * 1e-6)).collect();
+        samples.push(trade(0.002));
+        let rate = estimate(&without_outliers(samples.clone(), 5.)).unwrap();
+        assert!(rate < 0.00025, "{rate}");
+        assert!(estimate(&samples).unwrap() > 0.0015);
+        // a market trading at one rate keeps its neighbours
+        let flat: Vec<Sample> = [0.0002; 10]
+            .into_iter()
+            .chain([0.000201])
+            .map(trade)
+            .collect();
+        assert_eq!(without_outliers(flat, 5.).len(), 11);
+
+        assert!(cross_check(0.0006, &[("book", 0.0002), ("funding stats", 0.0005)], 3.).is_ok());
+        assert!(
+            cross_check(0.002, &[("book", 0.0002), ("funding stats", 0.0005)], 3.)
+                .unwrap_err()
+                .contains("book 0.0200% and funding stats 0.0500%")
+        );
+        assert!(cross_check(0.002, &[], 3.).is_ok());
+
+        let now = Utc::now();
+        let cooldown = Duration::minutes(15);
+        let mut breaker = Breaker::default();
+        assert_eq!(breaker.update(None, now, cooldown), None);
+        assert_eq!(
+            breaker.update(Some("stale".into()), now, cooldown),
+            Some("stale")
+        );
+        assert_eq!(
+            breaker.update(None, now + Duration::minutes(5), cooldown),
+            Some("stale")
+        );
+        assert_eq!(breaker.update(None, now + cooldown, cooldown), None);
+
+        // trades arrive with sync, an hourly sync leaves them up to an hour old
+        let hourly = Some(std::time::Duration::from_secs(3600));
+        assert_eq!(Config::default().stale(None), Duration::minutes(30));
+        assert_eq!(Config::default().stale(hourly), Duration::minutes(120));
+    }
+}
@@ -1,14 +1,279 @@
 pub mod lending;
+pub mod market;
 
-use anyhow::Result;
-use serde::Deserialize;
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use std::collections::VecDeque;
+use std::str::FromStr;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Duration;
 
-#[derive(Clone, Debug, Deserialize)]
-#[serde(tag = "name")]
+use crate::db::DbPool;
+use crate::exchange::{ExchangeApiClient, RequestFailed};
+use crate::notify::Notifier;
+use crate::risk::Risk;
+
+#[derive(Clone, Debug, PartialEq)]
 pub enum Config {
     Lending(lending::Config),
 }
 
-pub trait Strategy {
+impl<'de> Deserialize<'de> for Config {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let (name, fields) = crate::config::tagged(deserializer)?;
+        match name.as_str() {
+            "Lending" => Ok(Self::Lending(crate::config::variant(fields)?)),
+            _ => Err(de::Error::unknown_variant(&name, &["Lending"])),
+        }
+    }
+}
+
+impl Config {
+    pub fn symbol(&self) -> &str {
+        match self {
+            Self::Lending(config) => &config.symbol,
+        }
+    }
+
+    pub fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        match self {
+            Self::Lending(config) => config.validate(path, errors),
+        }
+    }
+
+    pub fn sync_schedule(&self) -> Schedule {
+        match self {
+            Self::Lending(config) => config.sync_schedule.clone().unwrap_or_default(),
+        }
+    }
+
+    pub fn exec_schedule(&self) -> Schedule {
+        match self {
+            Self::Lending(config) => config.offer_schedule.clone().unwrap_or_default(),
+        }
+    }
+
+    // `id` names the instance in logs and keys its persisted cursors
+    pub fn build(
+        &self,
+        id: String,
+        client: Arc<ExchangeApiClient>,
+        db_pool: DbPool,
+        notifier: Notifier,
+        risk: &Risk,
+    ) -> Result<Box<dyn Strategy + Send>> {
+        match self {
+            Self::Lending(config) => Ok(Box::new(lending::Strategy::new(
+                id,
+                client,
+                db_pool,
+                notifier,
+                risk,
+                config.clone(),
+            )?)),
+        }
+    }
+}
+
+// A fixed interval such as `15s`, `5m`, `1h` or `1d`, or a cron expression with seconds,
+// e.g. `0 */15 * * * *`. Cron expressions are evaluated in UTC.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Schedule {
+    Interval(Duration),
+    Cron(Box<cron::Schedule>),
+}
+
+impl Schedule {
+    // The interval, or the longest gap between the next runs of a cron expression
+    pub fn period(&self) -> Option<Duration> {
+        match self {
+            Self::Interval(interval) => Some(*interval),
+            Self::Cron(cron) => {
+                let runs: Vec<_> = cron.upcoming(Utc).take(10).collect();
+                runs.windows(2)
+                    .filter_map(|pair| (pair[1] - pair[0]).to_std().ok())
+                    .max()
+            }
+        }
+    }
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self::Interval(Duration::from_secs(60))
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let interval = s
+            .char_indices()
+            .last()
+            .and_then(|(idx, unit)| Some((s[..idx].parse::<u64>().ok()?, unit)))
+            .and_then(|(n, unit)| match unit {
+                's' => Some((n, 1)),
+                'm' => Some((n, 60)),
+                'h' => Some((n, 3600)),
+                'd' => Some((n, 86400)),
+                _ => None,
+            });
+
+        match interval {
+            Some((0, _)) => Err(anyhow!("schedule interval must be positive: {s:?}")),
+            Some((n, unit)) => n
+                .checked_mul(unit)
+                .map(|secs| Self::Interval(Duration::from_secs(secs)))
+                .ok_or_else(|| anyhow!("schedule interval is too long: {s:?}")),
+            None => cron::Schedule::from_str(s)
+                .map(|cron| Self::Cron(Box::new(cron)))
+                .map_err(|e| anyhow!("invalid schedule {s:?}: {e}")),
+        }
+    }
+}
+
+impl TryFrom<String> for Schedule {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+pub trait Strategy: std::fmt::Debug {
+    // record market data and account history
+    fn sync(&mut self) -> Result<()>;
+    // place, update or cancel orders
     fn exec(&mut self) -> Result<()>;
+    // withdraw everything placed and not yet filled
+    fn cancel_offers(&mut self) -> Result<()>;
+    fn status(&self) -> SharedStatus;
+}
+
+const MAX_DECISIONS: usize = 100;
+
+// What a strategy last did, readable without waiting for a running tick
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Status {
+    pub rate: Option<f64>,
+    pub rate_updated: Option<DateTime<Utc>>,
+    pub last_sync: Option<DateTime<Utc>>,
+    pub last_exec: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    // steps failed in a row on exchange requests
+    pub errors: u32,
+    // newest first
+    pub decisions: VecDeque<Decision>,
+    // newest first
+    pub failures: VecDeque<Failure>,
+    // why offers are paused by the market data checks
+    pub circuit_breaker: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Decision {
+    pub mts: DateTime<Utc>,
+    pub action: String,
+    pub detail: String,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct Failure {
+    pub mts: DateTime<Utc>,
+    pub step: String,
+    pub message: String,
+}
+
+impl Status {
+    pub fn set_rate(&mut self, rate: f64) {
+        self.rate = Some(rate);
+        self.rate_updated = Some(Utc::now());
+    }
+
+    pub fn decide(&mut self, action: &str, detail: String) {
+        self.decisions.push_front(Decision {
+            mts: Utc::now(),
+            action: action.into(),
+            detail,
+        });
+        self.decisions.truncate(MAX_DECISIONS);
+    }
+
+    // Only failed exchange requests count towards `errors`.
+    pub fn fail(&mut self, step: &str, err: &anyhow::Error) {
+        let message = format!("{:?}", err);
+        self.last_error = Some(message.clone());
+        if err.is::<RequestFailed>() {
+            self.errors += 1;
+        }
+        self.failures.push_front(Failure {
+            mts: Utc::now(),
+            step: step.into(),
+            message,
+        });
+        self.failures.truncate(MAX_DECISIONS);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct SharedStatus(Arc<RwLock<Status>>);
+
+impl SharedStatus {
+    pub fn read(&self) -> RwLockReadGuard<'_, Status> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, Status> {
+        self.0.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Schedule, Status};
+    use crate::exchange::RequestFailed;
+    use anyhow::anyhow;
+    use std::time::Duration;
+
+    #[test]
+    fn parse_schedule() {
+        assert_eq!(
+            "15s".parse::<Schedule>().unwrap(),
+            Schedule::Interval(Duration::from_secs(15))
+        );
+        assert_eq!(
+            "1h".parse::<Schedule>().unwrap(),
+            Schedule::Interval(Duration::from_secs(3600))
+        );
+        assert!(matches!(
+            "0 */15 * * * *".parse::<Schedule>().unwrap(),
+            Schedule::Cron(_)
+        ));
+        assert!("0s".parse::<Schedule>().is_err());
+        assert!("99999999999999999d".parse::<Schedule>().is_err());
+        assert!("every minute".parse::<Schedule>().is_err());
+        assert_eq!(
+            "0 0 9,17 * * *".parse::<Schedule>().unwrap().period(),
+            Some(Duration::from_secs(16 * 3600))
+        );
+    }
+
+    #[test]
+    fn count_request_failures() {
+        let mut status = Status::default();
+        status.fail(
+            "sync",
+            &RequestFailed(anyhow!("503 Service Unavailable")).into(),
+        );
+        status.fail("sync", &anyhow!("database is locked"));
+        assert_eq!(status.errors, 1);
+        assert_eq!(status.failures.len(), 2);
+        assert!(status
+            .last_error
+            .is_some_and(|e| e.starts_with("database is locked")));
+    }
 }
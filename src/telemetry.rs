@@ -0,0 +1,114 @@
+use anyhow::{anyhow, Result};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_sdk::Resource;
+use serde::Deserialize;
+use tracing::Level;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    #[default]
+    Text,
+    // one JSON object per line, with the fields of the enclosing spans
+    Json,
+}
+
+// `[logging]`, what is logged is still chosen with RUST_LOG, `error` if unset
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub format: Option<Format>,
+    // OTLP/HTTP traces endpoint of a collector, e.g. `http://localhost:4318/v1/traces`
+    pub otlp_endpoint: Option<String>,
+    // `tradebot` if omitted
+    pub service_name: Option<String>,
+}
+
+impl Config {
+    pub fn service_name(&self) -> &str {
+        self.service_name.as_deref().unwrap_or("tradebot")
+    }
+
+    pub fn validate(&self, path: &str, errors: &mut Vec<String>) {
+        if let Some(endpoint) = &self.otlp_endpoint {
+            if !reqwest::Url::parse(endpoint)
+                .is_ok_and(|url| matches!(url.scheme(), "http" | "https"))
+            {
+                errors.push(format!(
+                    "{path}.otlp_endpoint: {endpoint:?} is not an http(s) URL"
+                ));
+            }
+        }
+        if self.service_name.as_ref().is_some_and(|s| s.is_empty()) {
+            errors.push(format!("{path}.service_name: must not be empty"));
+        }
+    }
+}
+
+// Flushes the spans not yet exported when dropped, keep it until exiting.
+pub struct Guard(Option<SdkTracerProvider>);
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.0.take() {
+            if let Err(e) = provider.shutdown() {
+                eprintln!("failed to flush traces: {e:?}");
+            }
+        }
+    }
+}
+
+// Installs the global subscriber, records of the `log` macros included. Spans of this crate are
+// exported at info level regardless of RUST_LOG.
+pub fn init(config: Option<&Config>) -> Result<Guard> {
+    let config = config.cloned().unwrap_or_default();
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error"));
+    let fmt = match config.format.unwrap_or_default() {
+        Format::Text => tracing_subscriber::fmt::layer().boxed(),
+        Format::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(false)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    let provider = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(endpoint)
+                .build()
+                .map_err(|err| anyhow!("failed to create OTLP exporter: {:?}", err))?;
+            Some(
+                SdkTracerProvider::builder()
+                    .with_batch_exporter(exporter)
+                    .with_resource(
+                        Resource::builder()
+                            .with_service_name(config.service_name().to_string())
+                            .build(),
+                    )
+                    .build(),
+            )
+        }
+        None => None,
+    };
+    let otlp = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer()
+            .with_tracer(provider.tracer("tradebot"))
+            .with_filter(Targets::new().with_target("tradebot", Level::INFO))
+    });
+
+    tracing_subscriber::registry()
+        .with(fmt.with_filter(filter))
+        .with(otlp)
+        .try_init()
+        .map_err(|err| anyhow!("failed to install logger: {:?}", err))?;
+
+    Ok(Guard(provider))
+}